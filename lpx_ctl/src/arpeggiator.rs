@@ -0,0 +1,134 @@
+//! Play the notes of a section one after another while its pad is
+//! held.  There are no threads here: the main loop asks when the
+//! next note is due (`next_due`) and calls `tick` when it is
+use crate::section::{Arpeggio, ArpeggioOrder};
+use std::time::{Duration, Instant};
+
+/// An arpeggio that is playing
+#[derive(Debug)]
+struct Running {
+    /// Index of the section that started it
+    section: usize,
+
+    /// The notes in the order they are played.  It loops
+    sequence: Vec<u8>,
+
+    /// Index into `sequence` of the next note to play
+    index: usize,
+
+    /// The note that is on now, it needs a note-off before the next
+    sounding: Option<u8>,
+
+    step: Duration,
+    due: Instant,
+
+    /// Status byte (note-on, and channel) and velocity from the pad
+    status: u8,
+    velocity: u8,
+}
+
+impl Running {
+    /// Turn off the sounding note and start the next one
+    fn advance(&mut self) -> Vec<[u8; 3]> {
+        let mut result = vec![];
+        if let Some(note) = self.sounding.take() {
+            result.push([self.status, note, 0]);
+        }
+        let note = self.sequence[self.index];
+        result.push([self.status, note, self.velocity]);
+        self.sounding = Some(note);
+        self.index = (self.index + 1) % self.sequence.len();
+        result
+    }
+}
+
+/// All the arpeggios that are playing
+#[derive(Debug, Default)]
+pub struct Arpeggiator {
+    running: Vec<Running>,
+}
+
+impl Arpeggiator {
+    /// Build the order notes are played in from the notes of a
+    /// section
+    fn sequence(notes: &[u8], order: ArpeggioOrder) -> Vec<u8> {
+        let mut up = notes.to_vec();
+        up.sort();
+        match order {
+            ArpeggioOrder::Up => up,
+            ArpeggioOrder::Down => up.into_iter().rev().collect(),
+            ArpeggioOrder::UpDown => {
+                // Do not repeat the top and bottom notes
+                let mut result = up.clone();
+                if up.len() > 2 {
+                    result.extend(up[1..up.len() - 1].iter().rev());
+                }
+                result
+            }
+        }
+    }
+
+    /// Start playing `notes` for `section`.  Returns the MIDI for the
+    /// first note, which is played straight away
+    pub fn start(
+        &mut self,
+        section: usize,
+        notes: &[u8],
+        arpeggio: &Arpeggio,
+        status: u8,
+        velocity: u8,
+        now: Instant,
+    ) -> Vec<[u8; 3]> {
+        // Restarting a section that is running stops it first
+        let mut result = self.stop(section);
+        let sequence = Self::sequence(notes, arpeggio.order);
+        if sequence.is_empty() {
+            return result;
+        }
+        let step = Duration::from_millis(arpeggio.step_ms.max(1));
+        let mut running = Running {
+            section,
+            sequence,
+            index: 0,
+            sounding: None,
+            step,
+            due: now + step,
+            status,
+            velocity,
+        };
+        result.extend(running.advance());
+        self.running.push(running);
+        result
+    }
+
+    /// Stop the arpeggio for `section`.  Returns the note-off for the
+    /// note that is sounding
+    pub fn stop(&mut self, section: usize) -> Vec<[u8; 3]> {
+        let mut result = vec![];
+        if let Some(i) = self.running.iter().position(|r| r.section == section)
+        {
+            let running = self.running.remove(i);
+            if let Some(note) = running.sounding {
+                result.push([running.status, note, 0]);
+            }
+        }
+        result
+    }
+
+    /// Play every note that is due by `now`
+    pub fn tick(&mut self, now: Instant) -> Vec<[u8; 3]> {
+        let mut result = vec![];
+        for running in self.running.iter_mut() {
+            while running.due <= now {
+                result.extend(running.advance());
+                running.due += running.step;
+            }
+        }
+        result
+    }
+
+    /// When the next note is due, if any arpeggio is running
+    pub fn next_due(&self) -> Option<Instant> {
+        self.running.iter().map(|r| r.due).min()
+    }
+}
@@ -0,0 +1,184 @@
+//! The buttons on the top and side, mapped to controls.  A mapped
+//! button sends its CC on its channel, 127 when it turns on and 0 when
+//! it turns off, and is lit in its on or off colour.  A momentary
+//! button is on while it is held.  A toggle button turns on, or off,
+//! each time it is pressed.  Buttons that are not mapped send the
+//! LPX's control message as it is
+//!
+//! The buttons the sequencer and editor switch with, and the
+//! sequencer's others, can not be mapped.  The editor's other buttons
+//! do their own jobs first in edit mode.  (See `sequencer.rs` and
+//! `editor.rs`)
+use crate::colour::{Colour, Palette};
+use crate::editor;
+use crate::led::Led;
+use crate::lpx_ctl_error::LpxCtlError;
+use crate::sequencer;
+use serde::{Deserialize, Serialize};
+
+/// The buttons the sequencer and editor use
+const RESERVED: [u8; 5] = [
+    sequencer::MODE_CC,
+    sequencer::PLAY_CC,
+    sequencer::SAVE_CC,
+    editor::SESSION_CC,
+    editor::EDIT_CC,
+];
+
+/// Whether `button` is on the top or side of the LPX
+fn is_button(button: u8) -> bool {
+    (91..=98).contains(&button) || (19..=89).contains(&button) && button % 10 == 9
+}
+
+fn default_channel() -> u8 {
+    1
+}
+
+fn default_on_colour() -> Colour {
+    [0, 127, 0].into()
+}
+
+fn default_off_colour() -> Colour {
+    [8, 8, 8].into()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ButtonMode {
+    #[default]
+    Momentary,
+    Toggle,
+}
+
+/// A mapped button, from the configuration file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ButtonConfig {
+    /// The CC the LPX sends for the button.  91-98 along the top,
+    /// 19-89 down the side
+    pub button: u8,
+
+    /// The CC to send.  The button's own if not set
+    #[serde(default)]
+    pub cc: Option<u8>,
+
+    /// MIDI channel, 1-16
+    #[serde(default = "default_channel")]
+    pub channel: u8,
+
+    #[serde(default)]
+    pub mode: ButtonMode,
+
+    #[serde(default = "default_on_colour")]
+    pub on_colour: Colour,
+
+    #[serde(default = "default_off_colour")]
+    pub off_colour: Colour,
+}
+
+impl ButtonConfig {
+    /// Look up named colours, and check everything is in range
+    pub fn check(&mut self, palette: &Palette) -> Result<(), LpxCtlError> {
+        let bad = || LpxCtlError::BadButton(format!("{self:?}"));
+        if !is_button(self.button) || RESERVED.contains(&self.button) {
+            return Err(bad());
+        }
+        if self.cc.is_some_and(|cc| cc > 127) || !(1..=16).contains(&self.channel) {
+            return Err(bad());
+        }
+        for colour in [&mut self.on_colour, &mut self.off_colour] {
+            colour.resolve(palette)?;
+            colour.check()?;
+        }
+        Ok(())
+    }
+}
+
+/// The mapped buttons, and whether each is on
+#[derive(Debug, Default)]
+pub struct Buttons {
+    buttons: Vec<(ButtonConfig, bool)>,
+}
+
+impl Buttons {
+    pub fn new(config: &[ButtonConfig]) -> Self {
+        Self {
+            buttons: config.iter().map(|c| (c.clone(), false)).collect(),
+        }
+    }
+
+    /// `button` pressed (`value` > 0) or released.  The control
+    /// messages to send.  None if the button is not mapped
+    pub fn handle(&mut self, button: u8, value: u8) -> Option<Vec<[u8; 3]>> {
+        let (config, on) = self.buttons.iter_mut().find(|(c, _)| c.button == button)?;
+        let pressed = value > 0;
+        let now_on = match config.mode {
+            ButtonMode::Momentary => pressed,
+            ButtonMode::Toggle if pressed => !*on,
+            ButtonMode::Toggle => *on,
+        };
+        if now_on == *on {
+            return Some(vec![]);
+        }
+        *on = now_on;
+        let status = 0xB0 | (config.channel - 1);
+        let cc = config.cc.unwrap_or(config.button);
+        Some(vec![[status, cc, if now_on { 127 } else { 0 }]])
+    }
+
+    /// What the mapped buttons show
+    pub fn leds(&self) -> Vec<(u8, Led)> {
+        self.buttons
+            .iter()
+            .map(|(config, on)| {
+                let colour = if *on {
+                    &config.on_colour
+                } else {
+                    &config.off_colour
+                };
+                (config.button, colour.led())
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn momentary_and_toggle() {
+        let config: Vec<ButtonConfig> = serde_json::from_str(
+            r#"[
+                {"button": 91, "cc": 20, "channel": 2},
+                {"button": 29, "mode": "toggle", "on_colour": [127, 0, 0]}
+            ]"#,
+        )
+        .unwrap();
+        let mut buttons = Buttons::new(&config);
+        assert_eq!(buttons.handle(92, 127), None);
+
+        assert_eq!(buttons.handle(91, 127), Some(vec![[177, 20, 127]]));
+        assert_eq!(buttons.leds()[0], (91, [0, 127, 0].into()));
+        assert_eq!(buttons.handle(91, 0), Some(vec![[177, 20, 0]]));
+
+        // On with the first press, off with the second.  Releases do
+        // nothing
+        assert_eq!(buttons.handle(29, 127), Some(vec![[176, 29, 127]]));
+        assert_eq!(buttons.handle(29, 0), Some(vec![]));
+        assert_eq!(buttons.leds()[1], (29, [127, 0, 0].into()));
+        assert_eq!(buttons.handle(29, 127), Some(vec![[176, 29, 0]]));
+        assert_eq!(buttons.leds()[1], (29, [8, 8, 8].into()));
+
+        let mut bad = config[0].clone();
+        bad.channel = 17;
+        assert!(bad.check(&Palette::default()).is_err());
+        bad.channel = 16;
+        assert!(bad.check(&Palette::default()).is_ok());
+        // Pads, numbers that are not buttons, and the sequencer's and
+        // editor's buttons
+        for button in [11, 90, 99, 128, 98, 89, 19, 95, 96] {
+            bad.button = button;
+            assert!(bad.check(&Palette::default()).is_err());
+        }
+    }
+}
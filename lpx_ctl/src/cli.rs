@@ -0,0 +1,171 @@
+//! The command line.  `lpx_ctl <file>` is short for
+//! `lpx_ctl run <file>`
+use crate::config::Config;
+use crate::device::DeviceKind;
+use clap::{Args, CommandFactory, Parser, Subcommand};
+
+/// Use a Novation Launchpad as a musical instrument
+#[derive(Parser, Debug)]
+#[command(name = "lpx_ctl")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+
+    /// The grid controller, instead of the file's `device`
+    #[arg(long, global = true, value_enum)]
+    pub device: Option<DeviceKind>,
+
+    /// The MIDI channel, 1-16, notes are sent on, instead of the
+    /// file's `channel`
+    #[arg(long, global = true, value_parser = clap::value_parser!(u8).range(1..=16))]
+    pub channel: Option<u8>,
+
+    /// Semitones added to every note sent, instead of the file's
+    /// `note_offset`
+    #[arg(long, global = true, allow_negative_numbers = true)]
+    pub note_offset: Option<i8>,
+
+    /// The start of the MIDI client names, `<name>Note` and so on,
+    /// and the JACK client name.  Run each lpx_ctl with its own
+    #[arg(long, global = true)]
+    pub client_name: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run with the sections, and settings, in a file
+    Run {
+        file: String,
+        #[command(flatten)]
+        options: RunOptions,
+    },
+
+    /// Run with a generated scale layout
+    Scale {
+        root: String,
+        scale: String,
+        row_offset: String,
+
+        /// Print the sections as a section file, rather than running
+        #[arg(long)]
+        json: bool,
+
+        #[command(flatten)]
+        options: RunOptions,
+    },
+
+    /// Check a file can be run
+    Validate { file: String },
+
+    /// Print the sections of a file as a grid
+    Show { file: String },
+
+    /// List the MIDI ports, to find the device
+    ListPorts,
+
+    /// Play a script of pad presses into a simulated LPX and print
+    /// what is sent
+    Simulate { file: String, script: String },
+
+    /// Print a text layout as a section file
+    Convert { layout: String },
+
+    /// Play a log through the outputs the file configures
+    Replay { log: String, file: Option<String> },
+}
+
+#[derive(Args, Debug)]
+pub struct RunOptions {
+    /// Mirror the grid in the terminal
+    #[arg(long)]
+    pub tui: bool,
+
+    /// Log what is sent to the note and control outputs to this file
+    #[arg(long, value_name = "LOG")]
+    pub log: Option<String>,
+}
+
+impl Cli {
+    /// Parse `args`, the first being the programme's name.  If the
+    /// first argument that is not an option, or an option's value, is
+    /// not a subcommand it is the file to run
+    pub fn parse_args(mut args: Vec<String>) -> Self {
+        let command = Self::command();
+        // The options, of any subcommand, followed by a value
+        let takes_value: Vec<String> = command
+            .get_arguments()
+            .chain(command.get_subcommands().flat_map(|c| c.get_arguments()))
+            .filter(|a| a.get_action().takes_values())
+            .filter_map(|a| a.get_long().map(|long| format!("--{long}")))
+            .collect();
+        let mut rest = args.iter().skip(1);
+        let mut first = None;
+        while let Some(arg) = rest.next() {
+            if takes_value.contains(arg) {
+                rest.next();
+            } else if !arg.starts_with('-') {
+                first = Some(arg);
+                break;
+            }
+        }
+        if first.is_some_and(|f| f != "help" && command.find_subcommand(f).is_none()) {
+            args.insert(1, "run".to_string());
+        }
+        Self::parse_from(args)
+    }
+
+    /// The MIDI client names start with this
+    pub fn client_name(&self) -> &str {
+        self.client_name.as_deref().unwrap_or("LpxCtl")
+    }
+
+    /// Override the settings in `config` that are given
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(device) = self.device {
+            config.device = device;
+        }
+        if let Some(channel) = self.channel {
+            config.channel = Some(channel);
+        }
+        if let Some(note_offset) = self.note_offset {
+            config.note_offset = note_offset;
+        }
+        if let (Some(name), Some(jack)) = (self.client_name.as_ref(), config.jack.as_mut()) {
+            jack.client_name = name.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Cli {
+        Cli::parse_args(args.split(' ').map(String::from).collect())
+    }
+
+    #[test]
+    fn run_is_the_default() {
+        let cli = parse("lpx_ctl pads.json --tui --note-offset -12");
+        assert!(matches!(
+            cli.command,
+            Command::Run { ref file, options: RunOptions { tui: true, log: None } } if file == "pads.json"
+        ));
+        let mut config = Config::default();
+        cli.apply(&mut config);
+        assert_eq!(config.note_offset, -12);
+        assert_eq!(config.channel, None);
+
+        let cli = parse("lpx_ctl show pads.json --device launchpad_pro_mk3");
+        assert!(matches!(cli.command, Command::Show { .. }));
+        assert_eq!(cli.device, Some(DeviceKind::LaunchpadProMk3));
+        assert!(Cli::try_parse_from(["lpx_ctl", "list-ports", "--channel", "17"]).is_err());
+
+        // Options before the file
+        let cli = parse("lpx_ctl --channel 2 --note-offset -12 pads.json --tui");
+        assert!(matches!(cli.command, Command::Run { ref file, .. } if file == "pads.json"));
+        assert_eq!(cli.channel, Some(2));
+        let cli = parse("lpx_ctl --client-name Two show pads.json");
+        assert!(matches!(cli.command, Command::Show { .. }));
+    }
+}
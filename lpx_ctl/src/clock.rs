@@ -0,0 +1,114 @@
+//! MIDI clock.  Either generated here at a set tempo, or received on
+//! the virtual input `LpxCtlClock:port`.  Either way the clock is sent
+//! to the LPX, so its flashing and pulsing LEDs keep time, and to the
+//! virtual output `LpxCtlClockOut:port`
+use crate::lpx_ctl_error::LpxCtlError;
+use serde::{Deserialize, Serialize};
+use std::ops::RangeInclusive;
+use std::time::{Duration, Instant};
+
+/// MIDI clock sends 24 pulses a beat
+pub const PULSES_PER_BEAT: u32 = 24;
+
+/// The tempos that can be set, in beats a minute
+pub const BPM: RangeInclusive<f64> = 20.0..=999.0;
+
+/// MIDI real time messages
+pub const CLOCK: u8 = 0xF8;
+pub const START: u8 = 0xFA;
+pub const CONTINUE: u8 = 0xFB;
+pub const STOP: u8 = 0xFC;
+
+fn default_bpm() -> f64 {
+    120.0
+}
+
+/// How the clock is set up, from the configuration file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClockConfig {
+    /// Tempo of the generated clock
+    #[serde(default = "default_bpm")]
+    pub bpm: f64,
+
+    /// If true follow the clock received on `LpxCtlClock:port`, and
+    /// do not generate one
+    #[serde(default)]
+    pub input: bool,
+
+    /// If true create the virtual output `LpxCtlClockOut:port`
+    #[serde(default)]
+    pub output: bool,
+}
+
+impl ClockConfig {
+    /// Check the tempo is in range
+    pub fn check(&self) -> Result<(), LpxCtlError> {
+        if !BPM.contains(&self.bpm) {
+            return Err(LpxCtlError::BadBpm(self.bpm));
+        }
+        Ok(())
+    }
+}
+
+/// The generated clock
+#[derive(Debug)]
+pub struct Clock {
+    pulse: Duration,
+
+    /// When the next pulse is due.  None if the clock is not running
+    due: Option<Instant>,
+}
+
+impl Clock {
+    pub fn new(config: &ClockConfig) -> Self {
+        let pulse = Duration::from_secs_f64(
+            60.0 / config.bpm.max(1.0) / PULSES_PER_BEAT as f64,
+        );
+        Self { pulse, due: None }
+    }
+
+    /// Start generating pulses, the first one now
+    pub fn start(&mut self, now: Instant) {
+        self.due = Some(now);
+    }
+
+    /// The number of pulses due by `now`
+    pub fn tick(&mut self, now: Instant) -> u32 {
+        let mut result = 0;
+        while let Some(due) = self.due {
+            if due > now {
+                break;
+            }
+            result += 1;
+            self.due = Some(due + self.pulse);
+        }
+        result
+    }
+
+    /// When the next pulse is due
+    pub fn next_due(&self) -> Option<Instant> {
+        self.due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pulses() {
+        let config: ClockConfig = serde_json::from_str(r#"{"bpm": 60}"#).unwrap();
+        assert!(config.check().is_ok());
+        let mut clock = Clock::new(&config);
+        let now = Instant::now();
+        clock.start(now);
+        assert_eq!(clock.tick(now + Duration::from_millis(510)), 13);
+
+        // A tempo this fast would never catch up
+        for bpm in ["1e12", "0", "1000"] {
+            let bad: ClockConfig =
+                serde_json::from_str(&format!(r#"{{"bpm": {bpm}}}"#)).unwrap();
+            assert!(bad.check().is_err());
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! Colours in section files.  An RGB colour, `[r, g, b]` each in
+//! 0-127, an index into the LPX's built in palette of 128 colours, or
+//! the name of a colour in a palette file
+//!
+//! A palette file has one colour a line: `<r> <g> <b> <name>`, as in
+//! the layout files `make_cfg` reads.  Lines that do not start with a
+//! number are ignored, so those layout files can be used as palettes
+use crate::led::Led;
+use crate::lpx_ctl_error::LpxCtlError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Colour {
+    Rgb([u8; 3]),
+
+    /// The LPX's built in palette.  Sent as a palette LED message,
+    /// which is shorter than an RGB one
+    Index(u8),
+
+    /// From the palette file
+    Named(NamedColour),
+}
+
+/// A colour from the palette file.  Written to section files by
+/// name.  `rgb` is filled in from the palette when the file is loaded
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(from = "String", into = "String")]
+pub struct NamedColour {
+    pub name: String,
+    pub rgb: [u8; 3],
+}
+
+impl From<String> for NamedColour {
+    fn from(name: String) -> Self {
+        Self {
+            name,
+            rgb: [0, 0, 0],
+        }
+    }
+}
+
+impl From<NamedColour> for String {
+    fn from(colour: NamedColour) -> Self {
+        colour.name
+    }
+}
+
+impl From<[u8; 3]> for Colour {
+    fn from(colour: [u8; 3]) -> Self {
+        Colour::Rgb(colour)
+    }
+}
+
+impl Colour {
+    /// What a pad shows for this colour
+    pub fn led(&self) -> Led {
+        match self {
+            Colour::Rgb(colour) => Led::Rgb { colour: *colour },
+            Colour::Index(colour) => Led::Palette { colour: *colour },
+            Colour::Named(named) => Led::Rgb { colour: named.rgb },
+        }
+    }
+
+    /// The RGB colour.  None for a built in palette colour
+    pub fn rgb(&self) -> Option<[u8; 3]> {
+        match self {
+            Colour::Rgb(colour) => Some(*colour),
+            Colour::Index(_) => None,
+            Colour::Named(named) => Some(named.rgb),
+        }
+    }
+
+    /// Check every component, or the palette index, is in 0-127
+    pub fn check(&self) -> Result<(), LpxCtlError> {
+        self.led().check()
+    }
+
+    /// Fill in a named colour from `palette`
+    pub fn resolve(&mut self, palette: &Palette) -> Result<(), LpxCtlError> {
+        if let Colour::Named(named) = self {
+            named.rgb = palette.get(&named.name)?;
+        }
+        Ok(())
+    }
+}
+
+/// Colours by name.  (See the top of this file)
+#[derive(Debug, Clone, Default)]
+pub struct Palette {
+    colours: HashMap<String, [u8; 3]>,
+}
+
+impl Palette {
+    pub fn parse(input: &str) -> Result<Self, LpxCtlError> {
+        let mut result = Self::default();
+        for line in input.lines() {
+            let line = line.trim();
+            if !line.starts_with(|c: char| c.is_ascii_digit()) {
+                continue;
+            }
+            result.parse_line(line)?;
+        }
+        Ok(result)
+    }
+
+    /// Add a colour from a line `<r> <g> <b> <name>`
+    pub fn parse_line(&mut self, line: &str) -> Result<(), LpxCtlError> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.len() != 4 {
+            return Err(LpxCtlError::BadColour(line.to_string()));
+        }
+        let mut rgb = [0; 3];
+        for (c, word) in rgb.iter_mut().zip(words.iter()) {
+            *c = word
+                .parse()
+                .map_err(|_| LpxCtlError::BadColour(line.to_string()))?;
+        }
+        Colour::Rgb(rgb).check()?;
+        let name = words[3].to_string();
+        if self.colours.contains_key(&name) {
+            return Err(LpxCtlError::DuplicateName(name));
+        }
+        self.colours.insert(name, rgb);
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Result<[u8; 3], LpxCtlError> {
+        self.colours
+            .get(name)
+            .copied()
+            .ok_or_else(|| LpxCtlError::UnknownColour(name.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colours() {
+        let palette = Palette::parse("# Comment\n127 0 0 Red\n\n0 0 64 Navy_Blue\ns1 Red Red 60 [11]\n").unwrap();
+        let mut colours: Vec<Colour> =
+            serde_json::from_str(r#"[[1, 2, 3], 5, "Navy_Blue"]"#).unwrap();
+        for colour in colours.iter_mut() {
+            colour.resolve(&palette).unwrap();
+        }
+        assert_eq!(colours[0].led(), [1, 2, 3].into());
+        assert_eq!(colours[1].led(), Led::Palette { colour: 5 });
+        assert_eq!(colours[2].led(), [0, 0, 64].into());
+        assert_eq!(
+            serde_json::to_string(&colours).unwrap(),
+            r#"[[1,2,3],5,"Navy_Blue"]"#
+        );
+
+        let mut unknown = Colour::Named("Blue".to_string().into());
+        assert!(unknown.resolve(&palette).is_err());
+        assert!(Colour::Rgb([0, 128, 0]).check().is_err());
+        assert!(Colour::Index(128).check().is_err());
+        assert!(Palette::parse("128 0 0 Bright").is_err());
+        assert!(Palette::parse("1 0 0 Red\n2 0 0 Red").is_err());
+        assert!(Palette::parse("1 0 Red").is_err());
+    }
+}
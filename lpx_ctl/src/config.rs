@@ -0,0 +1,199 @@
+//! The configuration file.  It is either a JSON array of `Section`
+//! (see `section.rs`), or a JSON object with the sections in
+//! `sections` and the other settings alongside them
+use crate::buttons::ButtonConfig;
+use crate::clock::ClockConfig;
+use crate::colour::Palette;
+use crate::connections::ConnectionsConfig;
+use crate::device::DeviceKind;
+use crate::jack_midi::JackConfig;
+use crate::lpx_ctl_error::LpxCtlError;
+use crate::mod_host::ModHostConfig;
+use crate::section::Section;
+use crate::sequencer::SequencerConfig;
+use crate::text_layout;
+use crate::velocity::VelocityConfig;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::File;
+use std::io::prelude::*;
+
+fn is_zero(n: &i8) -> bool {
+    *n == 0
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Config {
+    pub sections: Vec<Section>,
+
+    /// The grid controller.  (See `device.rs`)
+    #[serde(default)]
+    pub device: DeviceKind,
+
+    /// If true create a virtual MIDI input, `LpxCtlFeedback:port`.
+    /// Notes received on it light the sections that play them
+    #[serde(default)]
+    pub feedback: bool,
+
+    /// The MIDI channel, 1-16, notes are sent on.  If not set the
+    /// channel the LPX sends on, 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<u8>,
+
+    /// Semitones added to every note sent.  Notes moved out of range
+    /// are not sent
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub note_offset: i8,
+
+    /// If set there is a step sequencer.  (See `sequencer.rs`)
+    #[serde(default)]
+    pub sequencer: Option<SequencerConfig>,
+
+    /// If set there is a MIDI clock.  (See `clock.rs`)
+    #[serde(default)]
+    pub clock: Option<ClockConfig>,
+
+    /// How the velocity of a press is changed, for sections that do
+    /// not set their own.  (See `velocity.rs`)
+    #[serde(default)]
+    pub velocity: Option<VelocityConfig>,
+
+    /// A file of named colours that sections can use.  (See
+    /// `colour.rs`)
+    #[serde(default)]
+    pub palette: Option<String>,
+
+    /// The buttons on the top and side that are mapped to controls.
+    /// (See `buttons.rs`)
+    #[serde(default)]
+    pub buttons: Vec<ButtonConfig>,
+
+    /// Where mod-host is, for sections that set LV2 plugin
+    /// parameters.  (See `mod_host.rs`)
+    #[serde(default)]
+    pub mod_host: Option<ModHostConfig>,
+
+    /// If set notes and controls are sent on JACK MIDI ports, not
+    /// ALSA virtual ports.  (See `jack_midi.rs`)
+    #[serde(default)]
+    pub jack: Option<JackConfig>,
+
+    /// Where the note and control outputs are connected to.  (See
+    /// `connections.rs`)
+    #[serde(default)]
+    pub connections: ConnectionsConfig,
+
+    /// The file this was loaded from.  The layout editor writes the
+    /// sections back to it.  (See `editor.rs`)
+    #[serde(skip)]
+    pub file: Option<String>,
+}
+
+impl Config {
+    /// Build a `Config` with default settings from `sections`
+    pub fn from_sections(sections: Vec<Section>) -> Self {
+        Self {
+            sections,
+            ..Default::default()
+        }
+    }
+
+    pub fn parse_json(input: &str) -> Result<Config, Box<dyn Error>> {
+        let mut result: Config = if input.trim_start().starts_with('[') {
+            // Only sections
+            Self::from_sections(Section::parse_json(input)?)
+        } else {
+            let result: Config = serde_json::from_str(input)?;
+            Section::check_each(&result.sections)?;
+            if !Section::check_sections(&result.sections) {
+                return Err(LpxCtlError::BadPads("Sections check failed".to_string()).into());
+            }
+            result
+        };
+        result.resolve_colours()?;
+        for section in result.sections.iter() {
+            if let Some(control) = section.control.as_ref() {
+                control.check(&section.pads)?;
+            }
+        }
+        if let Some(channel) = result.channel.filter(|c| !(1..=16).contains(c)) {
+            return Err(LpxCtlError::BadChannel(channel).into());
+        }
+        if let Some(sequencer) = result.sequencer.as_ref() {
+            sequencer.check()?;
+        }
+        if let Some(clock) = result.clock.as_ref() {
+            clock.check()?;
+        }
+        result.fill_default_section();
+        Ok(result)
+    }
+
+    /// A `Config` from a text layout, the format `make_cfg` reads.
+    /// (See `text_layout.rs`)
+    pub fn parse_text(input: &str) -> Result<Config, LpxCtlError> {
+        let sections = text_layout::parse(input)?;
+        Section::check_each(&sections)?;
+        if !Section::check_sections(&sections) {
+            return Err(LpxCtlError::BadPads("Sections check failed".to_string()));
+        }
+        let mut result = Self::from_sections(sections);
+        result.fill_default_section();
+        Ok(result)
+    }
+
+    /// Look up named colours in the palette file, and check every
+    /// colour, and mapped button, is in range
+    fn resolve_colours(&mut self) -> Result<(), Box<dyn Error>> {
+        let palette = match self.palette.as_ref() {
+            Some(filename) => {
+                let mut content = String::new();
+                File::open(filename)?.read_to_string(&mut content)?;
+                Palette::parse(&content)?
+            }
+            None => Palette::default(),
+        };
+        for section in self.sections.iter_mut() {
+            for colour in [&mut section.main_colour, &mut section.active_colour] {
+                colour.resolve(&palette)?;
+                colour.check()?;
+            }
+            for led in [section.main_led.as_ref(), section.active_led.as_ref()]
+                .into_iter()
+                .flatten()
+            {
+                led.check()?;
+            }
+        }
+        for button in self.buttons.iter_mut() {
+            button.check(&palette)?;
+        }
+        Ok(())
+    }
+
+    /// If there is a default section with no pads put all unincluded
+    /// pads in it
+    fn fill_default_section(&mut self) {
+        let sections: &mut Vec<Section> = &mut self.sections;
+        if let Some(index) = sections.iter().position(|x| x.pads.is_empty()) {
+            // Collect all pads mentioned so far
+            let mut pads_here: Vec<u8> =
+                sections.iter().flat_map(|x| x.pads.clone()).collect();
+            if pads_here.len() < 64 {
+                // Need the default
+                pads_here.sort();
+                // Check each row for missing pads and add them to default
+                for r in 1..=8 {
+                    let pads: Vec<&u8> =
+                        pads_here.iter().filter(|x| *x / 10 == r).collect();
+                    for c in 1..=8 {
+                        let pad = r * 10 + c;
+                        if !pads.iter().any(|x| x == &&pad) {
+                            sections[index].pads.push(pad);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
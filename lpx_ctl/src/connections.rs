@@ -0,0 +1,216 @@
+//! Connecting the note and control outputs to the programmes that use
+//! them, so a rig comes up with one command.  The destinations are
+//! found by patterns: Part of the port name, as the LPX is found.
+//! They are connected at start up, then looked for every `SCAN`, so
+//! they are reconnected when they reappear
+use crate::controller::MidiSink;
+use alsa::seq::{
+    Addr, ClientIter, MidiEvent, PortCap, PortIter, PortSubscribe, PortSubscribeIter, PortType,
+    QuerySubsType, Seq,
+};
+use alsa::Direction;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::ffi::CString;
+use std::thread;
+use std::time::Duration;
+
+/// How often destinations are looked for
+pub const SCAN: Duration = Duration::from_secs(1);
+
+/// The size of the ALSA MIDI encoder's buffer.  It grows for longer
+/// messages
+const ENCODER_BUFFER: usize = 32;
+
+/// Where the outputs are connected to, from the configuration file
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ConnectionsConfig {
+    /// Patterns for the destinations of the note output
+    #[serde(default)]
+    pub note: Vec<String>,
+
+    /// Patterns for the destinations of the control output
+    #[serde(default)]
+    pub ctl: Vec<String>,
+}
+
+/// Something that looks for destinations and connects an output to
+/// them
+pub trait Connector: Send {
+    /// What to look for
+    fn patterns(&self) -> &[String];
+
+    /// Connect to destinations that match and are not connected, and
+    /// forget those that have gone
+    fn scan(&mut self);
+}
+
+/// Scan now, then every `SCAN` for as long as the programme runs
+pub fn keep_connected(mut connectors: Vec<Box<dyn Connector>>) {
+    connectors.retain(|c| !c.patterns().is_empty());
+    if connectors.is_empty() {
+        return;
+    }
+    for connector in connectors.iter_mut() {
+        connector.scan();
+    }
+    thread::spawn(move || loop {
+        thread::sleep(SCAN);
+        for connector in connectors.iter_mut() {
+            connector.scan();
+        }
+    });
+}
+
+/// An ALSA sequencer client with a virtual output port.  What is sent
+/// goes to every port subscribed to it
+pub struct AlsaOutput {
+    seq: Seq,
+    port: i32,
+    encoder: MidiEvent,
+    buffer_size: usize,
+}
+
+impl MidiSink for AlsaOutput {
+    fn send(&mut self, message: &[u8]) -> Result<(), Box<dyn Error>> {
+        if message.len() > self.buffer_size {
+            self.encoder.resize_buffer(message.len() as u32)?;
+            self.buffer_size = message.len();
+        }
+        let mut event = match self.encoder.encode(message)? {
+            (_, Some(event)) => event,
+            _ => return Err(format!("Not a MIDI message: {message:?}").into()),
+        };
+        event.set_source(self.port);
+        event.set_subs();
+        event.set_direct();
+        self.seq.event_output_direct(&mut event)?;
+        Ok(())
+    }
+}
+
+/// The ports MIDI can be sent to, named as midir names them:
+/// `<client>:<port> <client number>:<port number>`
+fn destinations(seq: &Seq) -> Vec<(String, Addr)> {
+    let mut result = vec![];
+    for client in ClientIter::new(seq) {
+        let client_name = client.get_name().unwrap_or_default();
+        for port in PortIter::new(seq, client.get_client()) {
+            let midi = PortType::MIDI_GENERIC | PortType::SYNTH | PortType::APPLICATION;
+            if !port.get_type().intersects(midi)
+                || !port.get_capability().contains(PortCap::WRITE | PortCap::SUBS_WRITE)
+            {
+                continue;
+            }
+            let addr = port.addr();
+            let name = format!(
+                "{client_name}:{} {}:{}",
+                port.get_name().unwrap_or_default(),
+                addr.client,
+                addr.port
+            );
+            result.push((name, addr));
+        }
+    }
+    result
+}
+
+/// Connects an ALSA output by subscribing destinations to its port.
+/// It has its own client, `<client_name>Scan`, to look for them, so
+/// the output is not held up
+pub struct AlsaConnector {
+    /// The ALSA client name of the output
+    client_name: String,
+    patterns: Vec<String>,
+    seq: Seq,
+    port: Addr,
+
+    /// The names of the destinations connected
+    connected: Vec<String>,
+}
+
+impl AlsaConnector {
+    /// The output, the virtual port `<client_name>:<port_name>`, and
+    /// the connector for it
+    pub fn new(
+        client_name: &str,
+        port_name: &str,
+        patterns: &[String],
+    ) -> Result<(AlsaOutput, Self), Box<dyn Error>> {
+        let seq = Seq::open(None, Some(Direction::Playback), false)?;
+        seq.set_client_name(&CString::new(client_name)?)?;
+        let port = seq.create_simple_port(
+            &CString::new(port_name)?,
+            PortCap::READ | PortCap::SUBS_READ,
+            PortType::MIDI_GENERIC | PortType::APPLICATION,
+        )?;
+        let addr = Addr {
+            client: seq.client_id()?,
+            port,
+        };
+        let scan = Seq::open(None, Some(Direction::Playback), false)?;
+        scan.set_client_name(&CString::new(format!("{client_name}Scan"))?)?;
+        Ok((
+            AlsaOutput {
+                seq,
+                port,
+                encoder: MidiEvent::new(ENCODER_BUFFER as u32)?,
+                buffer_size: ENCODER_BUFFER,
+            },
+            Self {
+                client_name: client_name.to_string(),
+                patterns: patterns.to_vec(),
+                seq: scan,
+                port: addr,
+                connected: vec![],
+            },
+        ))
+    }
+
+    /// Whether the output is subscribed to `dest`, from here or by
+    /// hand
+    fn subscribed(&self, dest: Addr) -> bool {
+        PortSubscribeIter::new(&self.seq, self.port, QuerySubsType::READ)
+            .any(|s| s.get_dest() == dest)
+    }
+
+    fn connect(&self, dest: Addr) -> Result<(), Box<dyn Error>> {
+        let subscription = PortSubscribe::empty()?;
+        subscription.set_sender(self.port);
+        subscription.set_dest(dest);
+        Ok(self.seq.subscribe_port(&subscription)?)
+    }
+}
+
+impl Connector for AlsaConnector {
+    fn patterns(&self) -> &[String] {
+        &self.patterns
+    }
+
+    fn scan(&mut self) {
+        let destinations = destinations(&self.seq);
+        self.connected.retain(|name| {
+            let present = destinations.iter().any(|(n, _)| n == name);
+            if !present {
+                eprintln!("{name} has gone");
+            }
+            present
+        });
+        for (name, addr) in destinations {
+            if !self.patterns.iter().any(|p| name.contains(p.as_str()))
+                || self.connected.contains(&name)
+            {
+                continue;
+            }
+            // Tried again next scan if it fails
+            if !self.subscribed(addr) {
+                if let Err(err) = self.connect(addr) {
+                    eprintln!("{err}: Cannot connect {} to {name}", self.client_name);
+                    continue;
+                }
+                eprintln!("Connected {} to {name}", self.client_name);
+            }
+            self.connected.push(name);
+        }
+    }
+}
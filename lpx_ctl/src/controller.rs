@@ -0,0 +1,814 @@
+//! The state of the pads, sections, and everything else that drives
+//! the LPX and the MIDI outputs.  `main` feeds it `Event`s and wakes
+//! it up when something is due.  It only sees its outputs as
+//! `MidiSink`, so they can be real MIDI ports or a simulation (See
+//! `simulator.rs`)
+use crate::arpeggiator::Arpeggiator;
+use crate::buttons::Buttons;
+use crate::clock::{self, Clock};
+use crate::config::Config;
+use crate::controls::Controls;
+use crate::device::Device;
+use crate::editor::Editor;
+use crate::event::Event;
+use crate::event_log::EventLog;
+use crate::held_pads::HeldPads;
+use crate::led::Led;
+use crate::led_state::LedState;
+use crate::mod_host::{CommandSink, Params};
+use crate::section::Section;
+use crate::sequencer::{self, Sequencer};
+use crate::velocity::VelocityConfig;
+use midir::MidiOutputConnection;
+use std::cell::RefCell;
+use std::error::Error;
+use std::rc::Rc;
+use std::time::Instant;
+
+/// Somewhere to send MIDI
+pub trait MidiSink {
+    fn send(&mut self, message: &[u8]) -> Result<(), Box<dyn Error>>;
+}
+
+impl MidiSink for MidiOutputConnection {
+    fn send(&mut self, message: &[u8]) -> Result<(), Box<dyn Error>> {
+        Ok(MidiOutputConnection::send(self, message)?)
+    }
+}
+
+/// Where the controller sends MIDI
+pub struct Outputs {
+    /// To the LPX: Colours and mode changes
+    pub lpx: Box<dyn MidiSink>,
+
+    /// Notes, `LpxCtlNote:port`
+    pub note: Box<dyn MidiSink>,
+
+    /// Controls, `LpxCtlCtl:port`
+    pub ctl: Box<dyn MidiSink>,
+
+    /// MIDI clock, `LpxCtlClockOut:port`, if configured
+    pub clock: Option<Box<dyn MidiSink>>,
+
+    /// mod-host, if any section sets plugin parameters
+    pub mod_host: Option<Box<dyn CommandSink>>,
+}
+
+pub struct Controller {
+    sections: Vec<Section>,
+    device: Box<dyn Device>,
+    outputs: Outputs,
+
+    /// What the pads show.  Painting changes this, and the changes
+    /// are sent at the end of handling an event, or when due.  (See
+    /// `led_state.rs`)
+    leds: LedState,
+
+    /// Arpeggios that are playing.  When one is running the main loop
+    /// wakes up in time to play its next note
+    arpeggiator: Arpeggiator,
+
+    /// The pads held down in each section
+    held_pads: HeldPads,
+
+    /// The notes from the feedback input that are on, in each section.
+    /// A section is lit with its active colour while any pad is held
+    /// or any feedback note is on
+    feedback_notes: HeldPads,
+
+    /// The step sequencer, if it is configured.  (See `sequencer.rs`)
+    /// In sequencer mode the grid shows the pattern, not the sections
+    sequencer: Option<Sequencer>,
+    sequencer_mode: bool,
+
+    /// How the velocity of presses is changed, for sections that do
+    /// not set their own
+    velocity: Option<VelocityConfig>,
+
+    /// The channel, 1-16, notes are sent on, and the semitones added
+    /// to them.  (See `config.rs`)
+    channel: Option<u8>,
+    note_offset: i8,
+
+    /// The values of the faders and XY pads.  (See `controls.rs`)
+    controls: Controls,
+
+    /// The plugin parameters set in mod-host, and ramps under way.
+    /// (See `mod_host.rs`)
+    params: Params,
+
+    /// The buttons mapped to controls.  (See `buttons.rs`)
+    buttons: Buttons,
+
+    /// The layout editor.  In edit mode the grid shows the editor.
+    /// (See `editor.rs`)
+    editor: Editor,
+
+    /// The mode the device was in before programmer mode, as it
+    /// answered `mode_query`.  It is put back in it when stopping
+    previous_mode: Option<u8>,
+
+    /// The log of what is sent, if there is one.  It is told which pad
+    /// the messages are for.  (See `event_log.rs`)
+    log: Option<Rc<RefCell<EventLog>>>,
+
+    /// True if there is a MIDI clock configured.  (See `clock.rs`)
+    clock_configured: bool,
+
+    /// The generated clock, unless the clock is received on the clock
+    /// input
+    clock: Option<Clock>,
+}
+
+impl Controller {
+    pub fn new(config: Config, outputs: Outputs) -> Self {
+        let sequencer = config
+            .sequencer
+            .as_ref()
+            .map(|c| Sequencer::new(c, &config.sections));
+        let section_count = config.sections.len();
+        Self {
+            device: config.device.device(),
+            editor: Editor::new(config.file),
+            velocity: config.velocity,
+            channel: config.channel,
+            note_offset: config.note_offset,
+            buttons: Buttons::new(&config.buttons),
+            params: Params::default(),
+            controls: Controls::new(&config.sections, Instant::now()),
+            clock: config
+                .clock
+                .as_ref()
+                .filter(|c| !c.input)
+                .map(Clock::new),
+            clock_configured: config.clock.is_some(),
+            sections: config.sections,
+            outputs,
+            leds: LedState::default(),
+            arpeggiator: Arpeggiator::default(),
+            held_pads: HeldPads::new(section_count),
+            feedback_notes: HeldPads::new(section_count),
+            sequencer,
+            sequencer_mode: false,
+            previous_mode: None,
+            log: None,
+        }
+    }
+
+    /// Tell `log` which pads what is sent to the logged outputs is for
+    pub fn log_to(&mut self, log: Rc<RefCell<EventLog>>) {
+        self.log = Some(log);
+    }
+
+    /// Does the sequencer follow MIDI clock from the clock input.  If
+    /// so the input is needed even if no clock is configured
+    /// The sections, as the editor has left them
+    pub fn sections(&self) -> &[Section] {
+        &self.sections
+    }
+
+    pub fn sequencer_midi_clock(&self) -> bool {
+        self.sequencer
+            .as_ref()
+            .map(|s| s.midi_clock())
+            .unwrap_or(false)
+    }
+
+    /// Send to the LPX.  Failures are reported, not returned
+    fn send_lpx(&mut self, message: &[u8]) {
+        if let Err(err) = self.outputs.lpx.send(message) {
+            eprintln!("{err}: Failed to send msg to LPX: {message:?}");
+        }
+    }
+
+    fn send_notes(&mut self, notes: &[[u8; 3]]) -> Result<(), Box<dyn Error>> {
+        for message in notes.iter() {
+            self.send_note(message)?;
+        }
+        Ok(())
+    }
+
+    /// Send to the note output, on `channel` and with the note moved
+    /// by `note_offset`.  A note moved out of range is not sent
+    fn send_note(&mut self, message: &[u8]) -> Result<(), Box<dyn Error>> {
+        let mut message = message.to_vec();
+        let status = message[0] & 0xF0;
+        if let (Some(channel), 0x80..=0xE0) = (self.channel, status) {
+            message[0] = status | (channel - 1);
+        }
+        if [0x80, 0x90, 0xA0].contains(&status) && message.len() == 3 {
+            match message[1].checked_add_signed(self.note_offset) {
+                Some(note) if note < 128 => message[1] = note,
+                _ => return Ok(()),
+            }
+        }
+        self.outputs.note.send(&message)
+    }
+
+    /// Send to mod-host.  Failures are reported, not returned
+    fn send_mod_host(&mut self, commands: &[String]) {
+        if let Some(mod_host) = self.outputs.mod_host.as_mut() {
+            for command in commands.iter() {
+                if let Err(err) = mod_host.send(command) {
+                    eprintln!("{err}: Failed to send to mod-host: {command}");
+                }
+            }
+        }
+    }
+
+    /// Set what pads show
+    fn paint(&mut self, pads: &[(u8, Led)]) {
+        for (pad, led) in pads.iter() {
+            self.leds.set(*pad, *led);
+        }
+    }
+
+    /// Send the LEDs that have changed, if it is time to.  All in one
+    /// "LED lighting SysEx message"
+    fn send_leds(&mut self, now: Instant) {
+        let changes = self.leds.flush(now);
+        if !changes.is_empty() {
+            let message = self.device.led_message(&changes);
+            self.send_lpx(&message);
+        }
+    }
+
+    /// Set the colour of all the pads in a section (they are all the
+    /// same colour - part of what defines a section)
+    fn paint_section(&mut self, index: usize, led: Led) {
+        let pads: Vec<(u8, Led)> = self.sections[index]
+            .pads()
+            .iter()
+            .map(|pad| (*pad, led))
+            .collect();
+        self.paint(&pads);
+    }
+
+    /// Show the value of a fader, or XY pad
+    fn paint_control(&mut self, index: usize) {
+        let leds = self.controls.leds(index, &self.sections[index]);
+        self.paint(&leds);
+    }
+
+    /// Paint every section, with its active colour if it is active
+    fn paint_sections(&mut self) {
+        for index in 0..self.sections.len() {
+            let section = &self.sections[index];
+            if section.control.is_some() {
+                self.paint_control(index);
+                continue;
+            }
+            let led = if self.held_pads.active(index)
+                || self.feedback_notes.active(index)
+            {
+                section.active_led()
+            } else {
+                section.main_led()
+            };
+            self.paint_section(index, led);
+        }
+    }
+
+    fn paint_editor(&mut self) {
+        let leds = self.editor.leds(&self.sections);
+        self.paint(&leds);
+    }
+
+    /// The grid is showing the sections, not the sequencer or the
+    /// editor
+    fn showing_sections(&self) -> bool {
+        !self.sequencer_mode && !self.editor.editing()
+    }
+
+    /// The grid is showing the sequencer
+    fn showing_sequencer(&self) -> bool {
+        self.sequencer_mode && !self.editor.editing()
+    }
+
+    fn paint_sequencer(&mut self) {
+        if let Some(leds) = self.sequencer.as_ref().map(|s| s.leds()) {
+            self.paint(&leds);
+        }
+    }
+
+    /// Ask the LPX what mode it is in, then put it into programmer
+    /// mode.  It answers the question first
+    fn programmer_mode(&mut self) {
+        let msg = self.device.mode_query();
+        self.send_lpx(&msg);
+        let msg = self.device.programmer_mode();
+        self.send_lpx(&msg);
+    }
+
+    fn paint_buttons(&mut self) {
+        let leds = self.buttons.leds();
+        self.paint(&leds);
+    }
+
+    /// Put the LPX into programmer mode, set the colours, and start
+    /// the clock
+    pub fn start(&mut self, now: Instant) -> Result<(), Box<dyn Error>> {
+        self.programmer_mode();
+        self.paint_sections();
+        self.paint_buttons();
+        self.send_leds(now);
+        if let Some(clock) = self.clock.as_mut() {
+            clock.start(now);
+            self.send_lpx(&[clock::START]);
+            if let Some(port) = self.outputs.clock.as_mut() {
+                port.send(&[clock::START])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// When the next arpeggio note, sequencer step, clock pulse, ramp
+    /// step, control value, or LED message is due
+    pub fn next_due(&self) -> Option<Instant> {
+        [
+            self.leds.next_due(),
+            self.params.next_due(),
+            self.controls.next_due(),
+            self.arpeggiator.next_due(),
+            self.sequencer.as_ref().and_then(|s| s.next_due()),
+            self.clock.as_ref().and_then(|c| c.next_due()),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+
+    /// Do everything that is due by `now`
+    pub fn tick(&mut self, now: Instant) -> Result<(), Box<dyn Error>> {
+        let notes = self.arpeggiator.tick(now);
+        self.send_notes(&notes)?;
+        let commands = self.params.tick(now);
+        self.send_mod_host(&commands);
+        let (controls, moved) = self.controls.tick(&self.sections, now);
+        for control in controls.iter() {
+            self.outputs.ctl.send(control)?;
+        }
+        if self.showing_sections() {
+            for index in moved {
+                self.paint_control(index);
+            }
+        }
+        if let Some(sequencer) = self.sequencer.as_mut() {
+            let position = sequencer.position();
+            let notes = sequencer.tick(now);
+            let moved = position != sequencer.position();
+            self.send_notes(&notes)?;
+            if moved && self.showing_sequencer() {
+                self.paint_sequencer();
+            }
+        }
+        // Generated clock pulses are handled like those received on
+        // the clock input
+        let pulses = self.clock.as_mut().map(|c| c.tick(now)).unwrap_or(0);
+        for _ in 0..pulses {
+            self.handle_clock(&[clock::CLOCK], now)?;
+        }
+        self.send_leds(now);
+        Ok(())
+    }
+
+    pub fn handle(&mut self, event: Event, now: Instant) -> Result<(), Box<dyn Error>> {
+        let result = self.handle_event(event, now);
+        self.send_leds(now);
+        result
+    }
+
+    fn handle_event(&mut self, event: Event, now: Instant) -> Result<(), Box<dyn Error>> {
+        match event {
+            Event::Lpx(m) if m.first() == Some(&240) => {
+                // The answer to `mode_query`.  Unless the device was
+                // left in programmer mode
+                if let Some(mode) = self.device.mode_from_sysex(&m) {
+                    if mode != self.device.mode_command().1 {
+                        self.previous_mode = Some(mode);
+                    }
+                }
+                Ok(())
+            }
+            Event::Lpx(mut m) => {
+                // Notes and aftertouch from the device's pads are
+                // numbered 11-88 here
+                let mut pad = None;
+                if m.len() == 3 && [0x80, 0x90, 0xA0].contains(&(m[0] & 0xF0)) {
+                    match self.device.pad_from_note(m[1]) {
+                        Some(p) => m[1] = p,
+                        None => return Ok(()),
+                    }
+                    pad = Some(m[1]);
+                }
+                if let Some(log) = self.log.as_ref() {
+                    let section = pad.and_then(|p| self.sections.iter().position(|s| s.pad_in(p)));
+                    log.borrow_mut().cause(pad, section);
+                }
+                let result = self.handle_lpx(&m, now);
+                if let Some(log) = self.log.as_ref() {
+                    log.borrow_mut().cause(None, None);
+                }
+                result
+            }
+            Event::Clock(message) => self.handle_clock(&message, now),
+            Event::Feedback(message) => {
+                self.handle_feedback(&message);
+                Ok(())
+            }
+            Event::LpxGone => self.release_all(),
+            Event::LpxBack => {
+                // Programmer mode again, and show what it showed
+                self.programmer_mode();
+                self.leds.resend();
+                Ok(())
+            }
+            // `main` stops
+            Event::Quit => Ok(()),
+        }
+    }
+
+    /// Release every pad held down.  The LPX has gone, so their
+    /// releases will not come
+    fn release_all(&mut self) -> Result<(), Box<dyn Error>> {
+        for index in 0..self.sections.len() {
+            self.release_section(index)?;
+        }
+        Ok(())
+    }
+
+    /// Stop section `index` as if all its pads were released
+    fn release_section(&mut self, index: usize) -> Result<(), Box<dyn Error>> {
+        if !self.held_pads.clear(index) {
+            return Ok(());
+        }
+        let section = &self.sections[index];
+        let notes: Vec<[u8; 3]> = match section.arpeggio {
+            Some(_) => self.arpeggiator.stop(index),
+            None => section.notes().iter().map(|note| [144, *note, 0]).collect(),
+        };
+        self.send_notes(&notes)?;
+        if !self.feedback_notes.active(index) && self.showing_sections() {
+            let led = self.sections[index].main_led();
+            self.paint_section(index, led);
+        }
+        Ok(())
+    }
+
+    /// Section `index` has started.  Stop the other sections in its
+    /// choke group
+    fn choke(&mut self, index: usize) -> Result<(), Box<dyn Error>> {
+        let group = match self.sections[index].choke_group {
+            Some(group) => group,
+            None => return Ok(()),
+        };
+        for other in 0..self.sections.len() {
+            if other != index && self.sections[other].choke_group == Some(group) {
+                self.release_section(other)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Leave things as they were found, for the programme to finish.
+    /// Every note off, the LEDs off, and the LPX back in the mode it
+    /// was in.  Session (or Live) mode if it did not say.  The LPX is
+    /// left as it was found even if the other outputs fail, then the
+    /// first failure is returned and the rest printed
+    pub fn stop(&mut self, now: Instant) -> Result<(), Box<dyn Error>> {
+        let mut errors: Vec<Box<dyn Error>> = vec![];
+        errors.extend(self.release_all().err());
+        if let Some(sequencer) = self.sequencer.as_mut().filter(|s| s.playing()) {
+            let notes = sequencer.stop();
+            errors.extend(self.send_notes(&notes).err());
+        }
+        if let (Some(_), Some(port)) = (self.clock.as_ref(), self.outputs.clock.as_mut()) {
+            errors.extend(port.send(&[clock::STOP]).err());
+        }
+        // "All notes off" on every channel
+        for channel in 0..16 {
+            errors.extend(self.outputs.note.send(&[0xB0 | channel, 123, 0]).err());
+            errors.extend(self.outputs.ctl.send(&[0xB0 | channel, 123, 0]).err());
+        }
+
+        // Every pad and button, now
+        let off: Vec<(u8, Led)> = (1..=9)
+            .flat_map(|row| (1..=9).map(move |col| (row * 10 + col, Led::default())))
+            .collect();
+        self.paint(&off);
+        self.leds.resend();
+        self.send_leds(now);
+        let msg = self.device.mode_message(self.previous_mode.unwrap_or(0));
+        self.send_lpx(&msg);
+
+        let mut errors = errors.into_iter();
+        let first = errors.next();
+        for err in errors {
+            eprintln!("{err}: Failed to stop cleanly");
+        }
+        match first {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// A real time message from the clock input, or generated
+    fn handle_clock(&mut self, message: &[u8], now: Instant) -> Result<(), Box<dyn Error>> {
+        if message.len() != 1 {
+            // Only real time messages
+            return Ok(());
+        }
+        if self.clock_configured {
+            // Keep the LPX's animations in time, and pass the clock on
+            self.send_lpx(message);
+            if let Some(port) = self.outputs.clock.as_mut() {
+                port.send(message)?;
+            }
+        }
+        if let Some(sequencer) = self.sequencer.as_mut().filter(|s| s.midi_clock()) {
+            let m = message[0];
+            let position = sequencer.position();
+            let notes = sequencer.clock(m, now);
+            let moved = position != sequencer.position();
+            self.send_notes(&notes)?;
+            if (moved || m != clock::CLOCK) && self.showing_sequencer() {
+                self.paint_sequencer();
+            }
+        }
+        Ok(())
+    }
+
+    /// MIDI from the feedback input
+    fn handle_feedback(&mut self, message: &[u8]) {
+        if message.len() != 3 {
+            return;
+        }
+        let status = message[0] & 0xF0;
+        if status == 0x90 || status == 0x80 {
+            // Light, or restore, every section that plays the note.
+            // The notes played have been moved by `note_offset`
+            let on = status == 0x90 && message[2] > 0;
+            let note = match u8::try_from(message[1] as i16 - self.note_offset as i16) {
+                Ok(note) => note,
+                Err(_) => return,
+            };
+            for index in 0..self.sections.len() {
+                let section = &self.sections[index];
+                if section.control.is_some() || !section.notes().contains(&note) {
+                    continue;
+                }
+                let led = if on {
+                    section.active_led()
+                } else {
+                    section.main_led()
+                };
+                let edge = if on {
+                    self.feedback_notes.press(index, message[1])
+                } else {
+                    self.feedback_notes.release(index, message[1])
+                };
+                if edge && !self.held_pads.active(index) && self.showing_sections() {
+                    self.paint_section(index, led);
+                }
+            }
+        } else if status == 0xB0 && message[1] == 123 {
+            // All notes off
+            for index in 0..self.sections.len() {
+                if self.feedback_notes.clear(index)
+                    && !self.held_pads.active(index)
+                    && self.showing_sections()
+                {
+                    let led = self.sections[index].main_led();
+                    self.paint_section(index, led);
+                }
+            }
+        }
+    }
+
+    /// The sequencer buttons.  Act on the press, the release is not
+    /// passed on either.  Returns true if `message` was for the
+    /// sequencer
+    fn handle_sequencer_button(&mut self, message: &[u8], now: Instant) -> Result<bool, Box<dyn Error>> {
+        let sequencer = match self.sequencer.as_mut() {
+            Some(s) => s,
+            None => return Ok(false),
+        };
+        let button = message[1];
+        let pressed = message.get(2).map(|v| *v > 0).unwrap_or(false);
+        if button == sequencer::MODE_CC {
+            if pressed {
+                self.sequencer_mode = !self.sequencer_mode;
+                if self.sequencer_mode {
+                    // The sequencer takes the releases of pads held
+                    // now
+                    self.release_all()?;
+                    self.paint_sequencer();
+                } else {
+                    // Back to the sections
+                    self.paint_sections();
+                }
+            }
+        } else if button == sequencer::PLAY_CC {
+            if pressed {
+                if sequencer.playing() {
+                    let notes = sequencer.stop();
+                    self.send_notes(&notes)?;
+                } else {
+                    sequencer.start(now);
+                }
+                if self.sequencer_mode {
+                    self.paint_sequencer();
+                }
+            }
+        } else if button == sequencer::SAVE_CC {
+            if pressed {
+                if let Err(err) = sequencer.save() {
+                    eprintln!("{err}: Cannot save pattern");
+                }
+            }
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// MIDI from the LPX
+    fn handle_lpx(&mut self, message: &[u8], now: Instant) -> Result<(), Box<dyn Error>> {
+        let editing = self.editor.editing();
+        if self.editor.handle(message, &mut self.sections) {
+            if self.editor.editing() {
+                if !editing {
+                    // The editor takes the releases of pads held now
+                    self.release_all()?;
+                }
+                self.paint_editor();
+            } else if editing {
+                // Left edit mode
+                if self.sequencer_mode {
+                    self.paint_sequencer();
+                } else {
+                    self.paint_sections();
+                }
+            }
+            return Ok(());
+        }
+        if message[0] == 176 && self.handle_sequencer_button(message, now)? {
+            return Ok(());
+        }
+        if let (Some(sequencer), true) = (self.sequencer.as_mut(), self.sequencer_mode) {
+            // In sequencer mode pads turn steps on and off.  Buttons
+            // and pressure are handled as usual
+            if message[0] == 144 {
+                if message[2] > 0 {
+                    sequencer.toggle(message[1]);
+                    self.paint_sequencer();
+                }
+                return Ok(());
+            }
+        }
+        if message[0] == 144 {
+            // All MIDI notes from LPX start with 144, for initial
+            // noteon and noteoff
+
+            // Find the section the pad is in
+            let pad: u8 = message[1];
+
+            if let Some(index) = self.sections.iter().position(|x| x.pad_in(pad)) {
+                // got the section for a pad
+                let section = &self.sections[index];
+                if section.control.is_some() {
+                    // A fader, or XY pad, is set by presses
+                    if message[2] > 0 {
+                        let controls = self.controls.press(index, section, pad, now);
+                        for control in controls.iter() {
+                            self.outputs.ctl.send(control)?;
+                        }
+                        self.paint_control(index);
+                    }
+                    return Ok(());
+                }
+                let curve = section.velocity.as_ref().or(self.velocity.as_ref());
+                let mut velocity = curve.map(|c| c.apply(message[2])).unwrap_or(message[2]);
+                if section.radio {
+                    // A radio section is turned on by a press and off
+                    // by the next.  Releases do nothing
+                    if message[2] == 0 {
+                        return Ok(());
+                    }
+                    if self.held_pads.active(index) {
+                        velocity = 0;
+                    }
+                }
+
+                // A section is active while any of its pads are
+                // held.  It starts on the first press and stops on
+                // the last release.  A polyphonic section plays its
+                // notes for every pad
+                let (edge, led) = if velocity > 0 {
+                    let led = if curve.map(|c| c.colour).unwrap_or(false) {
+                        // As bright as the press is hard
+                        section.scaled_active_led(velocity)
+                    } else {
+                        section.active_led()
+                    };
+                    (self.held_pads.press(index, pad), led)
+                } else if section.radio {
+                    (self.held_pads.clear(index), section.main_led())
+                } else {
+                    (self.held_pads.release(index, pad), section.main_led())
+                };
+                if edge && velocity > 0 {
+                    self.choke(index)?;
+                }
+                let section = &self.sections[index];
+
+                // Send out the note, or notes
+                let notes: Vec<[u8; 3]> = match section.arpeggio.as_ref() {
+                    Some(_) if !edge => vec![],
+                    Some(arpeggio) if velocity > 0 => self.arpeggiator.start(
+                        index,
+                        &section.notes(),
+                        arpeggio,
+                        message[0],
+                        velocity,
+                        now,
+                    ),
+                    Some(_) => self.arpeggiator.stop(index),
+                    None if edge => section
+                        .notes()
+                        .iter()
+                        .map(|note| [message[0], *note, velocity])
+                        .collect(),
+                    // Every pad shares the notes, so a press while
+                    // they sound plays them again.  They stop on the
+                    // last release
+                    None if section.polyphonic && velocity > 0 => section
+                        .notes()
+                        .iter()
+                        .flat_map(|note| [[message[0], *note, 0], [message[0], *note, velocity]])
+                        .collect(),
+                    None => vec![],
+                };
+                self.send_notes(&notes)?;
+
+                // Set plugin parameters on the first press
+                if edge && velocity > 0 {
+                    let commands = self.params.press(&self.sections[index].mod_host, now);
+                    self.send_mod_host(&commands);
+                }
+
+                if edge && (velocity > 0 || !self.feedback_notes.active(index)) {
+                    // Set colour of section to "active_colour" on the
+                    // first press, restore it on the last release.
+                    // Unless feedback is keeping it lit
+                    self.paint_section(index, led);
+                }
+            }
+        } else if message[0] == 176 {
+            // A control signal.  Mapped buttons send their own
+            let value = message.get(2).copied().unwrap_or(0);
+            match self.buttons.handle(message[1], value) {
+                Some(controls) => {
+                    for control in controls.iter() {
+                        self.outputs.ctl.send(control)?;
+                    }
+                    self.paint_buttons();
+                }
+                None => {
+                    eprintln!("control_port On: Message{message:?}");
+                    self.outputs.ctl.send(message)?;
+                }
+            }
+        } else if message[0] & 0xF0 == 0xA0 && message.len() == 3 {
+            // Polyphonic aftertouch: The pressure on one pad.  Sent
+            // as aftertouch for the section's notes, or as a control
+            let pad: u8 = message[1];
+            let pressure = message[2];
+            if let Some(index) = self
+                .sections
+                .iter()
+                .position(|x| x.pad_in(pad) && x.control.is_none())
+            {
+                let section = &self.sections[index];
+                let messages = section.pressure_messages(message[0] & 0x0F, pressure);
+                let led = section.scaled_active_led(pressure);
+                let pressure_colour = section.pressure_colour;
+                self.send_notes(&messages)?;
+                if pressure_colour && self.held_pads.active(index) {
+                    self.paint_section(index, led);
+                }
+            }
+        } else if message[0] & 0xF0 == 0xD0 && message.len() == 2 {
+            // Channel pressure: The pressure on all the pads held
+            self.send_note(message)?;
+            for index in 0..self.sections.len() {
+                let section = &self.sections[index];
+                if section.pressure_colour && self.held_pads.active(index) {
+                    let led = section.scaled_active_led(message[1]);
+                    self.paint_section(index, led);
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,323 @@
+//! Sections that are continuous controls, not note triggers.  A fader
+//! is a row or column of pads.  Pressing one sets the control's value
+//! in proportion to how far along it is, and the pads up to it light
+//! in the section's active colour, a bar.  An XY pad is a rectangle of
+//! pads that sets two controls: Left to right and bottom to top.  The
+//! pad pressed lights up
+//!
+//! The values are sent as control changes on the control output.  So
+//! the steps between pads are not jumps, a control moves to its new
+//! value over `smoothing_ms`, sending a value every `STEP`
+use crate::led::Led;
+use crate::lpx_ctl_error::LpxCtlError;
+use crate::section::Section;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// The time between the values sent while a control moves
+pub const STEP: Duration = Duration::from_millis(20);
+
+fn default_channel() -> u8 {
+    1
+}
+
+fn default_smoothing_ms() -> u64 {
+    100
+}
+
+/// What kind of control, and the CCs it sends
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ControlKind {
+    Fader { cc: u8 },
+    Xy { x_cc: u8, y_cc: u8 },
+}
+
+/// A section's control, from the configuration file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ControlConfig {
+    #[serde(flatten)]
+    pub kind: ControlKind,
+
+    /// MIDI channel, 1-16
+    #[serde(default = "default_channel")]
+    pub channel: u8,
+
+    /// How long the control takes to move to a new value.  0 jumps
+    #[serde(default = "default_smoothing_ms")]
+    pub smoothing_ms: u64,
+}
+
+/// Where the pads are: The bottom left pad's row and column, and the
+/// number of columns and rows.  None if they do not fill a rectangle
+fn shape(pads: &[u8]) -> Option<(u8, u8, u8, u8)> {
+    let rows = pads.iter().map(|p| Section::pad_to_row(*p));
+    let cols = pads.iter().map(|p| Section::pad_to_col(*p));
+    let (row, col) = (rows.clone().min()?, cols.clone().min()?);
+    let height = rows.max()? - row + 1;
+    let width = cols.max()? - col + 1;
+    (pads.len() == width as usize * height as usize).then_some((row, col, width, height))
+}
+
+/// How far along `count` pads `position` is, as a value 0-127.  0
+/// if there is only one pad
+fn scale(position: u8, count: u8) -> f64 {
+    if count <= 1 {
+        return 0.0;
+    }
+    position as f64 * 127.0 / (count - 1) as f64
+}
+
+/// The position along `count` pads a value 0-127 is at
+fn unscale(value: u8, count: u8) -> u8 {
+    if count <= 1 {
+        return 0;
+    }
+    (value as f64 * (count - 1) as f64 / 127.0).round() as u8
+}
+
+impl ControlConfig {
+    /// The CCs sent, one for each axis
+    fn ccs(&self) -> Vec<u8> {
+        match self.kind {
+            ControlKind::Fader { cc } => vec![cc],
+            ControlKind::Xy { x_cc, y_cc } => vec![x_cc, y_cc],
+        }
+    }
+
+    /// Check the pads make the shape of the control, and the CCs and
+    /// channel are in range
+    pub fn check(&self, pads: &[u8]) -> Result<(), LpxCtlError> {
+        let bad = || LpxCtlError::BadControl(format!("{self:?} {pads:?}"));
+        let (_, _, width, height) = shape(pads).ok_or_else(bad)?;
+        let fits = match self.kind {
+            ControlKind::Fader { .. } => pads.len() > 1 && (width == 1 || height == 1),
+            ControlKind::Xy { .. } => width > 1 && height > 1,
+        };
+        if !fits || self.ccs().iter().any(|cc| *cc > 127) || !(1..=16).contains(&self.channel) {
+            return Err(bad());
+        }
+        Ok(())
+    }
+
+    /// The value of each axis for a press on `pad`
+    fn values(&self, pads: &[u8], pad: u8) -> Vec<f64> {
+        let (row, col, width, height) = shape(pads).unwrap_or((0, 0, 1, 1));
+        let x = Section::pad_to_col(pad) - col;
+        let y = Section::pad_to_row(pad) - row;
+        match self.kind {
+            ControlKind::Fader { .. } if height == 1 => vec![scale(x, width)],
+            ControlKind::Fader { .. } => vec![scale(y, height)],
+            ControlKind::Xy { .. } => vec![scale(x, width), scale(y, height)],
+        }
+    }
+
+    /// What the pads show for the values of the axes
+    fn leds(&self, pads: &[u8], values: &[u8], main: Led, active: Led) -> Vec<(u8, Led)> {
+        let (row, col, width, height) = match shape(pads) {
+            Some(s) => s,
+            None => return vec![],
+        };
+        pads.iter()
+            .map(|pad| {
+                let x = Section::pad_to_col(*pad) - col;
+                let y = Section::pad_to_row(*pad) - row;
+                let lit = match self.kind {
+                    ControlKind::Fader { .. } if height == 1 => x <= unscale(values[0], width),
+                    ControlKind::Fader { .. } => y <= unscale(values[0], height),
+                    ControlKind::Xy { .. } => {
+                        x == unscale(values[0], width) && y == unscale(values[1], height)
+                    }
+                };
+                (*pad, if lit { active } else { main })
+            })
+            .collect()
+    }
+}
+
+/// One value of a control, moving from `from` to `to`
+#[derive(Debug, Clone, Copy)]
+struct Axis {
+    from: f64,
+    to: f64,
+    start: Instant,
+
+    /// When the next value is due, if it is moving
+    next: Option<Instant>,
+
+    /// The last value sent
+    sent: u8,
+}
+
+impl Axis {
+    fn value(&self, now: Instant, smoothing: Duration) -> f64 {
+        let elapsed = now.saturating_duration_since(self.start);
+        if elapsed >= smoothing {
+            return self.to;
+        }
+        self.from + (self.to - self.from) * elapsed.as_secs_f64() / smoothing.as_secs_f64()
+    }
+
+    /// The value to send now, if it has changed
+    fn step(&mut self, now: Instant, smoothing: Duration) -> Option<u8> {
+        let value = self.value(now, smoothing).round() as u8;
+        self.next = (now < self.start + smoothing).then_some(now + STEP);
+        (value != self.sent).then(|| {
+            self.sent = value;
+            value
+        })
+    }
+}
+
+/// The values of the control sections
+#[derive(Debug)]
+pub struct Controls {
+    /// Indexed by section.  Empty for a section that is not a control
+    axes: Vec<Vec<Axis>>,
+}
+
+impl Controls {
+    pub fn new(sections: &[Section], now: Instant) -> Self {
+        let axis = Axis {
+            from: 0.0,
+            to: 0.0,
+            start: now,
+            next: None,
+            sent: 0,
+        };
+        Self {
+            axes: sections
+                .iter()
+                .map(|s| match s.control.as_ref() {
+                    Some(control) => vec![axis; control.ccs().len()],
+                    None => vec![],
+                })
+                .collect(),
+        }
+    }
+
+    /// `pad` in control section `section` (at `index`) is pressed.
+    /// The control changes to send now
+    pub fn press(
+        &mut self,
+        index: usize,
+        section: &Section,
+        pad: u8,
+        now: Instant,
+    ) -> Vec<[u8; 3]> {
+        let control = match section.control.as_ref() {
+            Some(c) => c,
+            None => return vec![],
+        };
+        let smoothing = Duration::from_millis(control.smoothing_ms);
+        for (axis, to) in self.axes[index]
+            .iter_mut()
+            .zip(control.values(&section.pads, pad))
+        {
+            axis.from = axis.value(now, smoothing);
+            axis.to = to;
+            axis.start = now;
+            axis.next = Some(now);
+        }
+        self.step(index, control, now)
+    }
+
+    /// The control changes due by `now` for section `index`
+    fn step(&mut self, index: usize, control: &ControlConfig, now: Instant) -> Vec<[u8; 3]> {
+        let smoothing = Duration::from_millis(control.smoothing_ms);
+        let status = 0xB0 | (control.channel - 1);
+        let mut result = vec![];
+        for (axis, cc) in self.axes[index].iter_mut().zip(control.ccs()) {
+            if axis.next.is_some_and(|t| t <= now) {
+                if let Some(value) = axis.step(now, smoothing) {
+                    result.push([status, cc, value]);
+                }
+            }
+        }
+        result
+    }
+
+    /// When the next value of a moving control is due
+    pub fn next_due(&self) -> Option<Instant> {
+        self.axes.iter().flatten().filter_map(|a| a.next).min()
+    }
+
+    /// The control changes due by `now`, and the sections whose
+    /// values changed
+    pub fn tick(&mut self, sections: &[Section], now: Instant) -> (Vec<[u8; 3]>, Vec<usize>) {
+        let mut messages = vec![];
+        let mut moved = vec![];
+        for (index, section) in sections.iter().enumerate() {
+            if let Some(control) = section.control.as_ref() {
+                let changes = self.step(index, control, now);
+                if !changes.is_empty() {
+                    moved.push(index);
+                }
+                messages.extend(changes);
+            }
+        }
+        (messages, moved)
+    }
+
+    /// What a control section's pads show
+    pub fn leds(&self, index: usize, section: &Section) -> Vec<(u8, Led)> {
+        let control = match section.control.as_ref() {
+            Some(c) => c,
+            None => return vec![],
+        };
+        let values: Vec<u8> = self.axes[index].iter().map(|a| a.sent).collect();
+        control.leds(
+            &section.pads,
+            &values,
+            section.main_led(),
+            section.active_led(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fader_and_xy() {
+        let mut fader = Section::new(vec![11, 21, 31, 41, 51], [1, 0, 0], [0, 1, 0], 60);
+        fader.control = Some(serde_json::from_str(r#"{"type": "fader", "cc": 7}"#).unwrap());
+        let mut xy = Section::new(vec![12, 13, 22, 23], [1, 0, 0], [0, 1, 0], 60);
+        xy.control = Some(
+            serde_json::from_str(
+                r#"{"type": "xy", "x_cc": 1, "y_cc": 2, "channel": 2, "smoothing_ms": 0}"#,
+            )
+            .unwrap(),
+        );
+        assert!(fader.control.as_ref().unwrap().check(&fader.pads).is_ok());
+        assert!(xy.control.as_ref().unwrap().check(&[12, 13, 22]).is_err());
+        assert!(xy.control.as_ref().unwrap().check(&[12, 13]).is_err());
+        assert_eq!(scale(0, 1), 0.0);
+        assert_eq!(unscale(127, 1), 0);
+
+        let start = Instant::now();
+        let sections = vec![fader, xy];
+        let mut controls = Controls::new(&sections, start);
+
+        // Smoothed: Half way after 50ms, there after 100ms
+        assert!(controls.press(0, &sections[0], 51, start).is_empty());
+        assert_eq!(controls.next_due(), Some(start + STEP));
+        let (messages, moved) = controls.tick(&sections, start + STEP * 3 / 2 + STEP);
+        assert_eq!(messages, vec![[176, 7, 64]]);
+        assert_eq!(moved, vec![0]);
+        let leds = controls.leds(0, &sections[0]);
+        assert_eq!(leds[2], (31, [0, 1, 0].into()));
+        assert_eq!(leds[3], (41, [1, 0, 0].into()));
+        let (messages, _) = controls.tick(&sections, start + STEP * 5);
+        assert_eq!(messages, vec![[176, 7, 127]]);
+        assert_eq!(controls.next_due(), None);
+
+        // Not smoothed
+        assert_eq!(
+            controls.press(1, &sections[1], 22, start),
+            vec![[177, 2, 127]]
+        );
+        assert_eq!(controls.leds(1, &sections[1])[2], (22, [0, 1, 0].into()));
+    }
+}
@@ -0,0 +1,165 @@
+//! The grid controllers that can be used.  They differ in the names
+//! of their MIDI ports, how they are put into programmer mode, the
+//! header of their SysEx messages, and how their pads are numbered.
+//! Pads are numbered here, and in section files, like the LPX: 11 is
+//! bottom left, 88 top right
+//!
+//! [Launchpad X](https://fael-downloads-prod.focusrite.com/customer/prod/s3fs-public/downloads/Launchpad%20X%20-%20Programmers%20Reference%20Manual.pdf)
+//! Launchpad Mini MK3 and Launchpad Pro MK3 have their own
+//! programmer's reference manuals
+use crate::led::Led;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// Novation's SysEx header.  The device ID follows it
+const NOVATION_SYSEX: [u8; 5] = [240, 0, 32, 41, 2];
+
+pub trait Device {
+    /// For people to read
+    fn name(&self) -> &'static str;
+
+    /// Part of the name of the device's MIDI port, used to find it
+    fn port_keyword(&self) -> &'static str;
+
+    /// The byte after `NOVATION_SYSEX` in every SysEx message
+    fn device_id(&self) -> u8;
+
+    /// The SysEx command that selects the device's mode, and the mode
+    /// that is programmer mode.  "Selecting Layouts" (page 7
+    /// programmers manual).  127 => "Programmer Mode"
+    fn mode_command(&self) -> (u8, u8) {
+        (0, 127)
+    }
+
+    /// The SysEx that puts the device into `mode`
+    fn mode_message(&self, mode: u8) -> Vec<u8> {
+        let mut message: Vec<u8> = NOVATION_SYSEX.to_vec();
+        message.extend([self.device_id(), self.mode_command().0, mode, 247]);
+        message
+    }
+
+    /// The SysEx that puts the device into programmer mode
+    fn programmer_mode(&self) -> Vec<u8> {
+        self.mode_message(self.mode_command().1)
+    }
+
+    /// The SysEx that asks the device what mode it is in.  The mode
+    /// command without the mode
+    fn mode_query(&self) -> Vec<u8> {
+        let mut message: Vec<u8> = NOVATION_SYSEX.to_vec();
+        message.extend([self.device_id(), self.mode_command().0, 247]);
+        message
+    }
+
+    /// The mode in the device's answer to `mode_query`, if `message`
+    /// is that
+    fn mode_from_sysex(&self, message: &[u8]) -> Option<u8> {
+        let body = message.strip_prefix(NOVATION_SYSEX.as_slice())?;
+        match body {
+            [id, command, mode, 247]
+                if *id == self.device_id() && *command == self.mode_command().0 =>
+            {
+                Some(*mode)
+            }
+            _ => None,
+        }
+    }
+
+    /// Build the MIDI command that sets the colours of a set of
+    /// pads.  One long MIDI sysex message that sets many pads in one
+    /// command.  The lighting types are the same on all of these
+    /// devices
+    fn led_message(&self, pads: &[(u8, Led)]) -> Vec<u8> {
+        // "LED lighting SysEx message" programmer's mabual page 15
+        let mut colour_message: Vec<u8> = NOVATION_SYSEX.to_vec();
+        colour_message.push(self.device_id());
+        colour_message.push(3);
+        for (pad, led) in pads.iter() {
+            // Lighting type, pad index, and colour
+            colour_message.extend(led.sysex(self.note_from_pad(*pad)));
+        }
+        colour_message.push(247); // End message
+        colour_message
+    }
+
+    /// The pad (11-88) for a note from the device.  None if it is
+    /// not on the 8x8 grid
+    fn pad_from_note(&self, note: u8) -> Option<u8> {
+        if (11..=88).contains(&note) && (1..=8).contains(&(note % 10)) {
+            Some(note)
+        } else {
+            None
+        }
+    }
+
+    /// The note the device uses for a pad (11-88)
+    fn note_from_pad(&self, pad: u8) -> u8 {
+        pad
+    }
+}
+
+pub struct LaunchpadX;
+impl Device for LaunchpadX {
+    fn name(&self) -> &'static str {
+        "Launchpad X"
+    }
+    fn port_keyword(&self) -> &'static str {
+        "Launchpad X LPX MIDI In"
+    }
+    fn device_id(&self) -> u8 {
+        12
+    }
+}
+
+/// Has no velocity or pressure.  Pads send velocity 127
+pub struct LaunchpadMiniMk3;
+impl Device for LaunchpadMiniMk3 {
+    fn name(&self) -> &'static str {
+        "Launchpad Mini MK3"
+    }
+    fn port_keyword(&self) -> &'static str {
+        "Launchpad Mini MK3 LPMiniMK3 MIDI In"
+    }
+    fn device_id(&self) -> u8 {
+        13
+    }
+}
+
+pub struct LaunchpadProMk3;
+impl Device for LaunchpadProMk3 {
+    fn name(&self) -> &'static str {
+        "Launchpad Pro MK3"
+    }
+    fn port_keyword(&self) -> &'static str {
+        "Launchpad Pro MK3 LPProMK3 MIDI In"
+    }
+    fn device_id(&self) -> u8 {
+        14
+    }
+    fn mode_command(&self) -> (u8, u8) {
+        // Programmer/Live mode switch.  1 => "Programmer Mode"
+        (14, 1)
+    }
+}
+
+/// Which device to use, in the configuration file or on the command
+/// line
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum DeviceKind {
+    #[default]
+    LaunchpadX,
+    LaunchpadMiniMk3,
+    LaunchpadProMk3,
+}
+
+impl DeviceKind {
+    pub fn device(&self) -> Box<dyn Device> {
+        match self {
+            DeviceKind::LaunchpadX => Box::new(LaunchpadX),
+            DeviceKind::LaunchpadMiniMk3 => Box::new(LaunchpadMiniMk3),
+            DeviceKind::LaunchpadProMk3 => Box::new(LaunchpadProMk3),
+        }
+    }
+}
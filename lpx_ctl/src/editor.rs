@@ -0,0 +1,327 @@
+//! Editing the layout on the LPX.  Hold "Session" (CC 95) and press
+//! "Note" (CC 96) to enter edit mode, and again to leave it.  Leaving
+//! writes the sections back to the configuration file, so only
+//! sections from a JSON file can be edited
+//!
+//! In edit mode the grid shows the sections, the one being edited in
+//! its active colour.  Pressing a pad moves it into the section being
+//! edited.  Holding "Session" and pressing a pad edits that pad's
+//! section instead.  Faders and XY pads keep their pads, so they keep
+//! their shape
+//! * Left and right (CC 93, 94): Edit the previous or next section
+//! * Up and down (CC 91, 92): Change the section's note by a semitone
+//! * The top two side buttons (CC 89, 79): Show a palette on the grid
+//!   to pick the main, or active, colour from
+use crate::led::Led;
+use crate::section::Section;
+use std::error::Error;
+use std::fs::File;
+use std::io::prelude::*;
+
+/// The buttons used
+pub const SESSION_CC: u8 = 95;
+pub const EDIT_CC: u8 = 96;
+const UP_CC: u8 = 91;
+const DOWN_CC: u8 = 92;
+const LEFT_CC: u8 = 93;
+const RIGHT_CC: u8 = 94;
+const MAIN_COLOUR_CC: u8 = 89;
+const ACTIVE_COLOUR_CC: u8 = 79;
+
+/// The colour palette.  A column for each hue, getting brighter up to
+/// row 7.  The top row is grey, from black to white
+const HUES: [[u8; 3]; 8] = [
+    [127, 0, 0],
+    [127, 40, 0],
+    [127, 127, 0],
+    [0, 127, 0],
+    [0, 127, 127],
+    [0, 0, 127],
+    [64, 0, 127],
+    [127, 0, 64],
+];
+
+/// The colour at a pad of the palette
+pub fn palette_colour(pad: u8) -> [u8; 3] {
+    let row = Section::pad_to_row(pad) as u16;
+    let col = Section::pad_to_col(pad) as usize - 1;
+    if row == 8 {
+        [(col * 127 / 7) as u8; 3]
+    } else {
+        HUES[col].map(|c| (c as u16 * row / 7) as u8)
+    }
+}
+
+/// What the grid is showing in edit mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum View {
+    Sections,
+    MainColour,
+    ActiveColour,
+}
+
+#[derive(Debug)]
+pub struct Editor {
+    /// The configuration file the sections are written to
+    file: Option<String>,
+
+    editing: bool,
+    view: View,
+
+    /// The index of the section being edited
+    selected: usize,
+
+    /// "Session" is held down
+    session_held: bool,
+
+    /// The release of "Note" after entering, or leaving, edit mode is
+    /// not passed on
+    swallow_release: bool,
+}
+
+impl Editor {
+    pub fn new(file: Option<String>) -> Self {
+        Self {
+            file,
+            editing: false,
+            view: View::Sections,
+            selected: 0,
+            session_held: false,
+            swallow_release: false,
+        }
+    }
+
+    /// The sections can be written back.  A text layout, or a
+    /// generated layout, has no JSON file to write them to
+    fn can_save(&self) -> bool {
+        self.file.as_ref().is_some_and(|f| !f.ends_with(".txt"))
+    }
+
+    /// In edit mode
+    pub fn editing(&self) -> bool {
+        self.editing
+    }
+
+    /// MIDI from the LPX.  Returns true if it was for the editor, so
+    /// must not be handled as usual
+    pub fn handle(&mut self, message: &[u8], sections: &mut [Section]) -> bool {
+        if message.len() != 3 {
+            return self.editing;
+        }
+        let pressed = message[2] > 0;
+        if message[0] == 176 {
+            let button = message[1];
+            if button == SESSION_CC {
+                self.session_held = pressed;
+            } else if button == EDIT_CC && self.session_held && pressed {
+                self.swallow_release = true;
+                if !self.editing && !self.can_save() {
+                    eprintln!("Cannot edit: The sections are not from a JSON file");
+                    return true;
+                }
+                self.editing = !self.editing;
+                self.view = View::Sections;
+                if !self.editing {
+                    if let Err(err) = self.save(sections) {
+                        eprintln!("{err}: Cannot save sections");
+                    }
+                }
+                return true;
+            } else if button == EDIT_CC && !pressed && self.swallow_release {
+                self.swallow_release = false;
+                return true;
+            }
+            if self.editing && pressed {
+                self.button(button, sections);
+            }
+        } else if message[0] == 144 && self.editing && pressed {
+            self.pad(message[1], sections);
+        }
+        self.editing
+    }
+
+    fn button(&mut self, button: u8, sections: &mut [Section]) {
+        let count = sections.len();
+        if count == 0 {
+            return;
+        }
+        let section = &mut sections[self.selected];
+        match button {
+            UP_CC if section.midi_note < 127 => section.midi_note += 1,
+            DOWN_CC if section.midi_note > 0 => section.midi_note -= 1,
+            LEFT_CC => self.selected = (self.selected + count - 1) % count,
+            RIGHT_CC => self.selected = (self.selected + 1) % count,
+            MAIN_COLOUR_CC => self.toggle_view(View::MainColour),
+            ACTIVE_COLOUR_CC => self.toggle_view(View::ActiveColour),
+            _ => return,
+        }
+        eprintln!(
+            "Editing section {} note {}",
+            self.selected, sections[self.selected].midi_note
+        );
+    }
+
+    fn toggle_view(&mut self, view: View) {
+        self.view = if self.view == view {
+            View::Sections
+        } else {
+            view
+        };
+    }
+
+    fn pad(&mut self, pad: u8, sections: &mut [Section]) {
+        match self.view {
+            View::Sections => {
+                let owner = sections.iter().position(|s| s.pad_in(pad));
+                if self.session_held {
+                    // Edit this pad's section
+                    if let Some(index) = owner {
+                        self.selected = index;
+                    }
+                } else if owner != Some(self.selected) && self.selected < sections.len() {
+                    // A section with no pads would be a second default
+                    // section, so a section's last pad stays
+                    if let Some(owner) = owner.filter(|o| sections[*o].pads.len() == 1) {
+                        eprintln!("Cannot move the last pad of section {owner}");
+                        return;
+                    }
+                    if let Some(control) = [owner, Some(self.selected)]
+                        .into_iter()
+                        .flatten()
+                        .find(|index| sections[*index].control.is_some())
+                    {
+                        eprintln!("Cannot move pads into or out of section {control}, a control");
+                        return;
+                    }
+                    for section in sections.iter_mut() {
+                        section.pads.retain(|p| *p != pad);
+                    }
+                    sections[self.selected].pads.push(pad);
+                }
+            }
+            View::MainColour | View::ActiveColour => {
+                if let Some(section) = sections.get_mut(self.selected) {
+                    let colour = palette_colour(pad);
+                    if self.view == View::MainColour {
+                        section.main_colour = colour.into();
+                        section.main_led = None;
+                    } else {
+                        section.active_colour = colour.into();
+                        section.active_led = None;
+                    }
+                }
+                self.view = View::Sections;
+            }
+        }
+    }
+
+    /// What the grid shows in edit mode
+    pub fn leds(&self, sections: &[Section]) -> Vec<(u8, Led)> {
+        let mut result = vec![];
+        for row in 1..=8 {
+            for col in 1..=8 {
+                let pad = Section::row_col_to_pad(row, col);
+                let led = match self.view {
+                    View::Sections => match sections.iter().position(|s| s.pad_in(pad)) {
+                        Some(index) if index == self.selected => sections[index].active_led(),
+                        Some(index) => sections[index].main_led(),
+                        None => Led::default(),
+                    },
+                    _ => palette_colour(pad).into(),
+                };
+                result.push((pad, led));
+            }
+        }
+        result
+    }
+
+    /// Write the sections into the configuration file.  Other
+    /// settings in it are kept
+    fn save(&self, sections: &[Section]) -> Result<(), Box<dyn Error>> {
+        let filename = match self.file.as_ref() {
+            Some(f) => f,
+            None => return Err("No configuration file".into()),
+        };
+        let mut content = String::new();
+        File::open(filename)?.read_to_string(&mut content)?;
+        let mut json: serde_json::Value = serde_json::from_str(&content)?;
+        let sections = serde_json::to_value(sections)?;
+        match json.as_object_mut() {
+            Some(object) => {
+                object.insert("sections".to_string(), sections);
+            }
+            // Only sections
+            None => json = sections,
+        }
+        let mut file = File::create(filename)?;
+        file.write_all(serde_json::to_string_pretty(&json)?.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sections() -> Vec<Section> {
+        vec![
+            Section::new(vec![11, 12], [1, 0, 0], [0, 1, 0], 60),
+            Section::new(vec![13], [0, 0, 1], [0, 1, 1], 62),
+        ]
+    }
+
+    #[test]
+    fn edit() {
+        let mut sections = sections();
+
+        // Not from a JSON file
+        for file in [None, Some("pads.txt".to_string())] {
+            let mut editor = Editor::new(file);
+            editor.handle(&[176, SESSION_CC, 127], &mut sections);
+            assert!(editor.handle(&[176, EDIT_CC, 127], &mut sections));
+            assert!(!editor.editing());
+        }
+
+        let mut editor = Editor::new(Some("pads.json".to_string()));
+        assert!(!editor.handle(&[176, SESSION_CC, 127], &mut sections));
+        assert!(editor.handle(&[176, EDIT_CC, 127], &mut sections));
+        assert!(editor.handle(&[176, SESSION_CC, 0], &mut sections));
+        assert!(editor.editing());
+
+        // Pad 13 is the second section's last, so it stays
+        editor.handle(&[144, 13, 127], &mut sections);
+        assert_eq!(sections[1].pads, vec![13]);
+
+        // Move pad 12 into the second section, and raise its note
+        editor.handle(&[176, RIGHT_CC, 127], &mut sections);
+        editor.handle(&[144, 12, 127], &mut sections);
+        editor.handle(&[176, UP_CC, 127], &mut sections);
+        assert_eq!(sections[0].pads, vec![11]);
+        assert_eq!(sections[1].pads, vec![13, 12]);
+        assert_eq!(sections[1].midi_note, 63);
+
+        // Pick the second section's main colour from the palette
+        editor.handle(&[176, MAIN_COLOUR_CC, 127], &mut sections);
+        assert_eq!(editor.leds(&sections)[0], (11, palette_colour(11).into()));
+        editor.handle(&[144, 88, 127], &mut sections);
+        assert_eq!(sections[1].main_colour, [127, 127, 127].into());
+        assert_eq!(editor.leds(&sections)[0], (11, [1, 0, 0].into()));
+    }
+
+    #[test]
+    fn controls_keep_their_pads() {
+        // Pads do not move out of, or into, a fader
+        for control in [0, 1] {
+            let mut sections = sections();
+            sections[control].control =
+                Some(serde_json::from_str(r#"{"type": "fader", "cc": 7}"#).unwrap());
+            let mut editor = Editor::new(Some("pads.json".to_string()));
+            editor.handle(&[176, SESSION_CC, 127], &mut sections);
+            editor.handle(&[176, EDIT_CC, 127], &mut sections);
+            editor.handle(&[176, SESSION_CC, 0], &mut sections);
+            editor.handle(&[176, RIGHT_CC, 127], &mut sections);
+            editor.handle(&[144, 12, 127], &mut sections);
+            assert_eq!(sections[0].pads, vec![11, 12]);
+        }
+    }
+}
@@ -0,0 +1,24 @@
+//! The things that the main loop handles.  They arrive on a channel
+//! from the threads that handle MIDI input
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// MIDI from the LPX
+    Lpx(Vec<u8>),
+
+    /// MIDI from the feedback input.  Notes light sections
+    Feedback(Vec<u8>),
+
+    /// MIDI real time messages from the clock input, or generated
+    /// (See `clock.rs`)
+    Clock(Vec<u8>),
+
+    /// The LPX has been unplugged.  (See `hotplug.rs`)
+    LpxGone,
+
+    /// The LPX is plugged in again, and reconnected
+    LpxBack,
+
+    /// Stop.  From the terminal display (See `tui.rs`)
+    Quit,
+}
@@ -0,0 +1,185 @@
+//! A log of everything sent to the note and control outputs, to see
+//! afterwards what was played, and to play it again.  One JSON object
+//! a line:
+//! `{"t":1520,"output":"note","message":[144,60,127],"pad":11,"section":0}`
+//! * t: Milliseconds from the start
+//! * output: "note" or "ctl"
+//! * message: The MIDI sent
+//! * pad, section: The pad pressed, and its section, that sent it.
+//!   Not there for what is sent by arpeggios, the sequencer, or
+//!   buttons
+//!
+//! `replay` plays a log back through the outputs, in time
+use crate::controller::MidiSink;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, prelude::*, LineWriter};
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A line of the log
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub t: u64,
+    pub output: String,
+    pub message: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pad: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub section: Option<usize>,
+}
+
+pub struct EventLog {
+    /// Written a line at a time, so the log is whole if lpx_ctl
+    /// stops suddenly
+    file: LineWriter<File>,
+    start: Instant,
+
+    /// What caused the messages being sent now
+    pad: Option<u8>,
+    section: Option<usize>,
+}
+
+impl EventLog {
+    pub fn create(filename: &str) -> io::Result<Self> {
+        Ok(Self {
+            file: LineWriter::new(File::create(filename)?),
+            start: Instant::now(),
+            pad: None,
+            section: None,
+        })
+    }
+
+    /// The pad, and section, that the messages sent from now on are
+    /// for.  None when they are not for a pad
+    pub fn cause(&mut self, pad: Option<u8>, section: Option<usize>) {
+        self.pad = pad;
+        self.section = section;
+    }
+
+    /// Failures are reported, not returned, so they do not stop the
+    /// MIDI
+    fn write(&mut self, output: &str, message: &[u8]) {
+        let entry = Entry {
+            t: self.start.elapsed().as_millis() as u64,
+            output: output.to_string(),
+            message: message.to_vec(),
+            pad: self.pad,
+            section: self.section,
+        };
+        let result = serde_json::to_string(&entry)
+            .map_err(io::Error::from)
+            .and_then(|line| writeln!(self.file, "{line}"));
+        if let Err(err) = result {
+            eprintln!("{err}: Failed to log {entry:?}");
+        }
+    }
+}
+
+/// An output that logs what is sent to it
+pub struct LoggedSink {
+    /// The name of the output in the log
+    pub output: &'static str,
+    pub sink: Box<dyn MidiSink>,
+    pub log: Rc<RefCell<EventLog>>,
+}
+
+impl MidiSink for LoggedSink {
+    fn send(&mut self, message: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.log.borrow_mut().write(self.output, message);
+        self.sink.send(message)
+    }
+}
+
+/// Read a log
+pub fn read(input: &str) -> Result<Vec<Entry>, Box<dyn Error>> {
+    let mut result = vec![];
+    for (index, line) in input.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: Entry =
+            serde_json::from_str(line).map_err(|err| format!("{}: {err}", index + 1))?;
+        result.push(entry);
+    }
+    Ok(result)
+}
+
+/// Play `entries` through the outputs, at the times they were sent.
+/// Then "all notes off" on every channel, in case the log ends with
+/// notes on
+pub fn replay(
+    entries: &[Entry],
+    note: &mut dyn MidiSink,
+    ctl: &mut dyn MidiSink,
+) -> Result<(), Box<dyn Error>> {
+    let start = Instant::now();
+    for entry in entries.iter() {
+        let due = start + Duration::from_millis(entry.t);
+        thread::sleep(due.saturating_duration_since(Instant::now()));
+        match entry.output.as_str() {
+            "note" => note.send(&entry.message)?,
+            "ctl" => ctl.send(&entry.message)?,
+            output => eprintln!("Unknown output {output}: {entry:?}"),
+        }
+    }
+    for channel in 0..16 {
+        note.send(&[0xB0 | channel, 123, 0])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records what is sent
+    struct Messages(Rc<RefCell<Vec<Vec<u8>>>>);
+
+    impl MidiSink for Messages {
+        fn send(&mut self, message: &[u8]) -> Result<(), Box<dyn Error>> {
+            self.0.borrow_mut().push(message.to_vec());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn log_and_replay() {
+        let filename = std::env::temp_dir().join(format!("lpx_ctl_log_{}.jsonl", std::process::id()));
+        let filename = filename.to_str().unwrap();
+        let log = Rc::new(RefCell::new(EventLog::create(filename).unwrap()));
+        let sent = Rc::new(RefCell::new(vec![]));
+        let mut note = LoggedSink {
+            output: "note",
+            sink: Box::new(Messages(sent.clone())),
+            log: log.clone(),
+        };
+        log.borrow_mut().cause(Some(11), Some(0));
+        note.send(&[144, 60, 127]).unwrap();
+        log.borrow_mut().cause(None, None);
+        note.send(&[144, 60, 0]).unwrap();
+        drop(note);
+        drop(log);
+
+        let mut content = String::new();
+        File::open(filename).unwrap().read_to_string(&mut content).unwrap();
+        std::fs::remove_file(filename).unwrap();
+        let entries = read(&content).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].message, vec![144, 60, 127]);
+        assert_eq!((entries[0].pad, entries[0].section), (Some(11), Some(0)));
+        assert_eq!(entries[1].pad, None);
+        assert!(!content.lines().nth(1).unwrap().contains("pad"));
+
+        // Played back, then all notes off
+        let played = Rc::new(RefCell::new(vec![]));
+        let mut note = Messages(played.clone());
+        let mut ctl = Messages(played.clone());
+        replay(&entries, &mut note, &mut ctl).unwrap();
+        assert_eq!(played.borrow()[..2], sent.borrow()[..]);
+        assert_eq!(played.borrow().len(), 18);
+    }
+}
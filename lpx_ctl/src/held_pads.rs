@@ -0,0 +1,49 @@
+//! Keep track of which pads are held down in each section.  A
+//! section is active from when its first pad is pressed until its
+//! last pad is released
+use std::collections::HashSet;
+
+#[derive(Debug, Default)]
+pub struct HeldPads {
+    /// Indexed by section.  The pads held down in that section
+    held: Vec<HashSet<u8>>,
+}
+
+impl HeldPads {
+    pub fn new(section_count: usize) -> Self {
+        Self {
+            held: vec![HashSet::new(); section_count],
+        }
+    }
+
+    /// Record `pad` in `section` is pressed.  Returns true if no
+    /// other pad in the section was held: the section has become
+    /// active
+    pub fn press(&mut self, section: usize, pad: u8) -> bool {
+        let held = &mut self.held[section];
+        let first = held.is_empty();
+        held.insert(pad);
+        first
+    }
+
+    /// Record `pad` in `section` is released.  Returns true if it
+    /// was the last pad held in the section: the section is no longer
+    /// active.  A release for a pad that was not held returns false
+    pub fn release(&mut self, section: usize, pad: u8) -> bool {
+        let held = &mut self.held[section];
+        held.remove(&pad) && held.is_empty()
+    }
+
+    /// Is any pad in `section` held down
+    pub fn active(&self, section: usize) -> bool {
+        !self.held[section].is_empty()
+    }
+
+    /// Release every pad in `section`.  Returns true if any were held
+    pub fn clear(&mut self, section: usize) -> bool {
+        let held = &mut self.held[section];
+        let active = !held.is_empty();
+        held.clear();
+        active
+    }
+}
@@ -0,0 +1,74 @@
+//! Following the LPX when it is unplugged and plugged back in.  A
+//! thread looks for its port every `POLL` and tells the main loop when
+//! it goes, or comes back.  The main loop reconnects to it, and the
+//! controller puts it back in programmer mode and repaints it.  The
+//! outputs for notes and controls stay open throughout
+use crate::controller::MidiSink;
+use crate::event::Event;
+use midir::{MidiInput, MidiOutputConnection};
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// How often the LPX is looked for
+pub const POLL: Duration = Duration::from_secs(1);
+
+/// The output to the LPX, that can be reconnected.  While the LPX is
+/// unplugged what is sent to it is dropped
+#[derive(Clone, Default)]
+pub struct LpxOutput {
+    connection: Arc<Mutex<Option<MidiOutputConnection>>>,
+}
+
+impl LpxOutput {
+    /// Send to `connection` from now on.  `None` when it has gone
+    pub fn set(&self, connection: Option<MidiOutputConnection>) {
+        *self.connection.lock().unwrap() = connection;
+    }
+}
+
+impl MidiSink for LpxOutput {
+    fn send(&mut self, message: &[u8]) -> Result<(), Box<dyn Error>> {
+        match self.connection.lock().unwrap().as_mut() {
+            Some(connection) => Ok(connection.send(message)?),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Look for a port with `keyword` in its name every `POLL`.
+/// `connected` is whether the main loop is connected to the LPX.
+/// `Event::LpxGone` is sent when it is and the port has gone, and
+/// `Event::LpxBack` when it is not and the port is there.  The MIDI
+/// client looking is `<client_name>Watch`
+pub fn watch(
+    keyword: &str,
+    client_name: &str,
+    connected: Arc<AtomicBool>,
+    tx: Sender<Event>,
+) -> Result<(), Box<dyn Error>> {
+    let midi_input = MidiInput::new(&format!("{client_name}Watch"))?;
+    let keyword = keyword.to_string();
+    thread::spawn(move || loop {
+        thread::sleep(POLL);
+        let present = midi_input.ports().iter().any(|p| {
+            midi_input
+                .port_name(p)
+                .map(|n| n.contains(&keyword))
+                .unwrap_or(false)
+        });
+        let event = match (connected.load(Ordering::SeqCst), present) {
+            (true, false) => Event::LpxGone,
+            (false, true) => Event::LpxBack,
+            _ => continue,
+        };
+        if tx.send(event).is_err() {
+            // The main loop has finished
+            break;
+        }
+    });
+    Ok(())
+}
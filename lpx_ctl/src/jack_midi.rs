@@ -0,0 +1,228 @@
+//! JACK MIDI outputs for notes and controls, instead of the ALSA
+//! virtual ports.  So they can go straight to JACK clients, like
+//! midi_sample and mod-host, without bridging them with a2jmidid
+//!
+//! The JACK client has two MIDI output ports: `<client name>:notes`
+//! and `<client name>:controls`.  MIDI is passed to the JACK process
+//! thread in a lock free queue, and sent at the start of the next
+//! period.  The process thread neither allocates nor prints.  What
+//! does not fit in a period's buffer waits for the next, and the
+//! times that happens are reported when MIDI is next sent
+use crate::connections::{ConnectionsConfig, Connector};
+use crate::controller::MidiSink;
+use jack::{
+    AsyncClient, Client, ClientOptions, Control, MidiOut, Port, PortFlags, ProcessHandler,
+    ProcessScope, RawMidi, RingBuffer, RingBufferReader, RingBufferWriter,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::error::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// The longest MIDI message that can be sent.  Notes and controls are
+/// three bytes
+const MESSAGE_SIZE: usize = 3;
+
+/// A message in the queue: Its length, then its bytes
+const ENTRY_SIZE: usize = MESSAGE_SIZE + 1;
+
+/// How many messages the queue holds
+const QUEUE_LENGTH: usize = 1024;
+
+fn default_client_name() -> String {
+    "LpxCtl".to_string()
+}
+
+/// How JACK is set up, from the configuration file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JackConfig {
+    #[serde(default = "default_client_name")]
+    pub client_name: String,
+
+    /// JACK ports to connect the notes output to.  E.g.
+    /// "MidiSampleQzn3t:midi_in".  Used as patterns, along with
+    /// `connections`.  (See `connections.rs`)
+    #[serde(default)]
+    pub notes: Vec<String>,
+
+    /// JACK ports to connect the controls output to
+    #[serde(default)]
+    pub controls: Vec<String>,
+}
+
+/// One JACK MIDI output, and where its MIDI comes from
+struct Output {
+    port: Port<MidiOut>,
+    queue: RingBufferReader,
+
+    /// The periods the port's buffer filled up in
+    full: Arc<AtomicUsize>,
+}
+
+impl Output {
+    fn process(&mut self, ps: &ProcessScope) {
+        let mut writer = self.port.writer(ps);
+        let mut entry = [0; ENTRY_SIZE];
+        while self.queue.peek(&mut entry) == ENTRY_SIZE {
+            let bytes = &entry[1..=entry[0] as usize];
+            if writer.write(&RawMidi { time: 0, bytes }).is_err() {
+                // Left in the queue for the next period
+                self.full.fetch_add(1, Ordering::Relaxed);
+                break;
+            }
+            self.queue.advance(ENTRY_SIZE);
+        }
+    }
+}
+
+/// Runs in the JACK process thread
+struct Process {
+    outputs: Vec<Output>,
+}
+
+impl ProcessHandler for Process {
+    fn process(&mut self, _: &Client, ps: &ProcessScope) -> Control {
+        for output in self.outputs.iter_mut() {
+            output.process(ps);
+        }
+        Control::Continue
+    }
+}
+
+/// Sends MIDI to a JACK output
+pub struct JackSink {
+    queue: RingBufferWriter,
+    full: Arc<AtomicUsize>,
+}
+
+impl JackSink {
+    /// A sink and the output it sends to
+    fn new(port: Port<MidiOut>) -> Result<(Self, Output), Box<dyn Error>> {
+        let (reader, writer) = RingBuffer::new(ENTRY_SIZE * QUEUE_LENGTH)?.into_reader_writer();
+        let full = Arc::new(AtomicUsize::new(0));
+        Ok((
+            Self {
+                queue: writer,
+                full: full.clone(),
+            },
+            Output {
+                port,
+                queue: reader,
+                full,
+            },
+        ))
+    }
+}
+
+impl MidiSink for JackSink {
+    fn send(&mut self, message: &[u8]) -> Result<(), Box<dyn Error>> {
+        let full = self.full.swap(0, Ordering::Relaxed);
+        if full > 0 {
+            eprintln!("JACK MIDI buffer full {full} times: Sent late");
+        }
+        if message.is_empty() || message.len() > MESSAGE_SIZE {
+            return Err(format!("Cannot send {message:?} to JACK MIDI").into());
+        }
+        if self.queue.space() < ENTRY_SIZE {
+            return Err(format!("JACK MIDI queue full: {message:?} not sent").into());
+        }
+        let mut entry = [0; ENTRY_SIZE];
+        entry[0] = message.len() as u8;
+        entry[1..=message.len()].copy_from_slice(message);
+        self.queue.write_buffer(&entry);
+        Ok(())
+    }
+}
+
+/// The JACK client.  The ports are there as long as it is
+pub struct JackMidi {
+    client: AsyncClient<(), Process>,
+
+    /// The full names of the ports
+    names: Vec<String>,
+}
+
+impl JackMidi {
+    /// Start the client, and make the sinks for notes and controls
+    pub fn new(config: &JackConfig) -> Result<(Arc<Self>, JackSink, JackSink), Box<dyn Error>> {
+        let (client, _status) =
+            Client::new(&config.client_name, ClientOptions::NO_START_SERVER)?;
+        let mut outputs = vec![];
+        let mut sinks = vec![];
+        let mut names = vec![];
+        for name in ["notes", "controls"] {
+            let port = client.register_port(name, MidiOut)?;
+            names.push(port.name()?);
+            let (sink, output) = JackSink::new(port)?;
+            outputs.push(output);
+            sinks.push(sink);
+        }
+        let client = client.activate_async((), Process { outputs })?;
+        let controls = sinks.pop().unwrap();
+        let notes = sinks.pop().unwrap();
+        Ok((Arc::new(Self { client, names }), notes, controls))
+    }
+
+    /// The connectors for the notes and controls outputs
+    pub fn connectors(
+        self: &Arc<Self>,
+        config: &JackConfig,
+        connections: &ConnectionsConfig,
+    ) -> Vec<Box<dyn Connector>> {
+        let patterns = [
+            [config.notes.as_slice(), connections.note.as_slice()].concat(),
+            [config.controls.as_slice(), connections.ctl.as_slice()].concat(),
+        ];
+        self.names
+            .iter()
+            .zip(patterns)
+            .map(|(source, patterns)| {
+                Box::new(JackConnector {
+                    jack: self.clone(),
+                    source: source.clone(),
+                    patterns,
+                    connected: HashSet::new(),
+                }) as Box<dyn Connector>
+            })
+            .collect()
+    }
+}
+
+/// Connects a JACK output to the JACK MIDI inputs that match
+struct JackConnector {
+    jack: Arc<JackMidi>,
+    source: String,
+    patterns: Vec<String>,
+
+    /// The ports connected to
+    connected: HashSet<String>,
+}
+
+impl Connector for JackConnector {
+    fn patterns(&self) -> &[String] {
+        &self.patterns
+    }
+
+    fn scan(&mut self) {
+        let client = self.jack.client.as_client();
+        let ports = client.ports(None, Some("midi"), PortFlags::IS_INPUT);
+        self.connected.retain(|p| ports.contains(p));
+        for port in ports.iter() {
+            if !self.patterns.iter().any(|p| port.contains(p.as_str()))
+                || self.connected.contains(port)
+            {
+                continue;
+            }
+            match client.connect_ports_by_name(&self.source, port) {
+                Ok(()) => eprintln!("Connected {} to {port}", self.source),
+                Err(jack::Error::PortAlreadyConnected(..)) => (),
+                Err(err) => {
+                    eprintln!("{err}: Cannot connect {} to {port}", self.source);
+                    continue;
+                }
+            }
+            self.connected.insert(port.clone());
+        }
+    }
+}
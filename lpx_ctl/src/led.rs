@@ -0,0 +1,108 @@
+//! What a pad's LED shows.  An RGB colour, or one of the LPX's
+//! animations.  Animations use colours from the LPX's built in
+//! palette and follow the MIDI clock sent to it
+//! (Programmer's manual page 15)
+use crate::lpx_ctl_error::LpxCtlError;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Led {
+    /// A colour.  Each in 0-127
+    Rgb { colour: [u8; 3] },
+
+    /// Flash between two palette colours, once a beat
+    Flash {
+        colour: u8,
+        #[serde(default)]
+        other: u8,
+    },
+
+    /// Pulse a palette colour, once a beat
+    Pulse { colour: u8 },
+
+    /// A colour from the palette.  Each pad takes three bytes, not
+    /// five as RGB does
+    Palette { colour: u8 },
+}
+
+impl Led {
+    /// The part of a "LED lighting SysEx message" that sets `pad` to
+    /// this
+    pub fn sysex(&self, pad: u8) -> Vec<u8> {
+        match self {
+            Led::Rgb { colour } => vec![3, pad, colour[0], colour[1], colour[2]],
+            Led::Flash { colour, other } => vec![1, pad, *other, *colour],
+            Led::Pulse { colour } => vec![2, pad, *colour],
+            Led::Palette { colour } => vec![0, pad, *colour],
+        }
+    }
+
+    /// Check every colour is in 0-127
+    pub fn check(&self) -> Result<(), LpxCtlError> {
+        let ok = match self {
+            Led::Rgb { colour } => colour.iter().all(|c| *c < 128),
+            Led::Flash { colour, other } => *colour < 128 && *other < 128,
+            Led::Pulse { colour } | Led::Palette { colour } => *colour < 128,
+        };
+        if ok {
+            Ok(())
+        } else {
+            Err(LpxCtlError::ColourOutOfRange(format!("{self:?}")))
+        }
+    }
+
+    /// The pads, and what they are set to, in a "LED lighting SysEx
+    /// message".  Empty if `message` is something else
+    pub fn from_sysex(message: &[u8]) -> Vec<(u8, Led)> {
+        let mut result = vec![];
+        // Header: 240, 0, 32, 41, 2, <device>, 3.  Then for each pad:
+        // <lighting type>, <pad>, <colour...>.  Then 247
+        if message.len() < 8 || message[..5] != [240, 0, 32, 41, 2] || message[6] != 3 {
+            return result;
+        }
+        let mut i = 7;
+        while i + 1 < message.len() && message[i] != 247 {
+            let pad = message[i + 1];
+            let length = match message[i] {
+                0 => 3,
+                1 => 4,
+                2 => 3,
+                3 => 5,
+                _ => break,
+            };
+            if i + length > message.len() {
+                break;
+            }
+            let led = match message[i] {
+                0 => Led::Palette {
+                    colour: message[i + 2],
+                },
+                1 => Led::Flash {
+                    other: message[i + 2],
+                    colour: message[i + 3],
+                },
+                2 => Led::Pulse {
+                    colour: message[i + 2],
+                },
+                _ => [message[i + 2], message[i + 3], message[i + 4]].into(),
+            };
+            result.push((pad, led));
+            i += length;
+        }
+        result
+    }
+}
+
+/// Off
+impl Default for Led {
+    fn default() -> Self {
+        Led::Rgb { colour: [0, 0, 0] }
+    }
+}
+
+impl From<[u8; 3]> for Led {
+    fn from(colour: [u8; 3]) -> Self {
+        Led::Rgb { colour }
+    }
+}
@@ -0,0 +1,101 @@
+//! What the LPX's pads are showing, and the changes waiting to be
+//! sent.  Only pads that change are sent, and changes made within a
+//! frame of the last LED message are sent together in the next one.
+//! So large sections, and fast drumming, do not flood the MIDI link
+use crate::led::Led;
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+/// The shortest time between LED messages
+pub const FRAME: Duration = Duration::from_millis(5);
+
+#[derive(Debug, Default)]
+pub struct LedState {
+    /// What has been sent to each pad
+    shown: HashMap<u8, Led>,
+
+    /// Changes not sent yet
+    pending: BTreeMap<u8, Led>,
+
+    /// When the last LED message was sent
+    last_sent: Option<Instant>,
+}
+
+impl LedState {
+    /// Set what a pad shows
+    pub fn set(&mut self, pad: u8, led: Led) {
+        if self.shown.get(&pad) == Some(&led) {
+            self.pending.remove(&pad);
+        } else {
+            self.pending.insert(pad, led);
+        }
+    }
+
+    /// Send every pad again, with the next changes.  After the LPX
+    /// has been plugged back in, and has forgotten what it showed
+    pub fn resend(&mut self) {
+        for (pad, led) in std::mem::take(&mut self.shown) {
+            self.pending.entry(pad).or_insert(led);
+        }
+        self.last_sent = None;
+    }
+
+    /// When the changes waiting must be sent
+    pub fn next_due(&self) -> Option<Instant> {
+        if self.pending.is_empty() {
+            None
+        } else {
+            self.last_sent.map(|t| t + FRAME)
+        }
+    }
+
+    /// The changes to send now.  Empty if there are none, or it is
+    /// within a frame of the last LED message
+    pub fn flush(&mut self, now: Instant) -> Vec<(u8, Led)> {
+        if self.pending.is_empty() || self.last_sent.is_some_and(|t| now < t + FRAME) {
+            return vec![];
+        }
+        self.last_sent = Some(now);
+        let changes: Vec<(u8, Led)> = std::mem::take(&mut self.pending).into_iter().collect();
+        self.shown.extend(changes.iter().copied());
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_and_coalesce() {
+        let red: Led = [127, 0, 0].into();
+        let green: Led = [0, 127, 0].into();
+        let start = Instant::now();
+        let mut state = LedState::default();
+        state.set(11, red);
+        state.set(12, red);
+        assert_eq!(state.flush(start), vec![(11, red), (12, red)]);
+
+        // Unchanged pads are not sent again.  Changes within a frame
+        // wait for the next one
+        state.set(11, red);
+        state.set(12, green);
+        assert_eq!(state.flush(start), vec![]);
+        assert_eq!(state.next_due(), Some(start + FRAME));
+        state.set(13, green);
+        assert_eq!(state.flush(start + FRAME), vec![(12, green), (13, green)]);
+
+        // Changed and changed back
+        state.set(12, red);
+        state.set(12, green);
+        assert_eq!(state.next_due(), None);
+
+        // Everything again, at once
+        state.set(11, green);
+        state.resend();
+        assert_eq!(
+            state.flush(start + FRAME),
+            vec![(11, green), (12, green), (13, green)]
+        );
+    }
+}
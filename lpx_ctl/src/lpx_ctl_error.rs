@@ -9,6 +9,56 @@ pub enum LpxCtlError {
     // IntersectingSections, // Sections intersect
     // DuplicateMainColour,  // > 1 section same colour NOT AN ERROR FIXME
     // DuplicateMIDI,        // >1 section same MIDI NOT AN ERROR FIXME
+    /// A scale, or scale layout, that is not known
+    UnknownScale(String),
+
+    /// A generated layout would need a note above 127
+    NoteOutOfRange(u8),
+
+    /// No MIDI port with a name containing this
+    DeviceNotFound(String),
+
+    /// A colour, or palette index, with a value above 127
+    ColourOutOfRange(String),
+
+    /// A line of a palette that is not `<r> <g> <b> <name>`
+    BadColour(String),
+
+    /// A colour name that is not in the palette
+    UnknownColour(String),
+
+    /// A name defined twice
+    DuplicateName(String),
+
+    /// A list of pads with a pad that is not on the grid, or that is
+    /// in more than one section
+    BadPads(String),
+
+    /// A line of a text layout, and its line number, that can not be
+    /// understood
+    BadLayoutLine(usize, String),
+
+    /// A section, and its index, with a pad that is not on the grid, a
+    /// pad twice, or a note above 127
+    BadSection(usize, String),
+
+    /// A sequencer track, and its index, with a note or velocity above
+    /// 127
+    BadTrack(usize, String),
+
+    /// A mapped button that is not a button, or with a CC or channel
+    /// out of range
+    BadButton(String),
+
+    /// A fader, or XY pad, whose pads are not its shape, or with a CC
+    /// or channel out of range
+    BadControl(String),
+
+    /// A MIDI channel that is not 1-16
+    BadChannel(u8),
+
+    /// A tempo that is not in `clock::BPM`
+    BadBpm(f64),
 }
 
 impl fmt::Display for LpxCtlError {
@@ -25,6 +75,27 @@ impl fmt::Display for LpxCtlError {
             // LpxCtlError::DuplicateMIDI => {
             //     write!(f, "duplicate MIDI")
             // }
+            LpxCtlError::UnknownScale(s) => write!(f, "unknown scale: {s}"),
+            LpxCtlError::NoteOutOfRange(pad) => {
+                write!(f, "note out of range for pad {pad}")
+            }
+            LpxCtlError::DeviceNotFound(keyword) => {
+                write!(f, "no MIDI port found matching: {keyword}")
+            }
+            LpxCtlError::ColourOutOfRange(c) => {
+                write!(f, "colour out of range 0-127: {c}")
+            }
+            LpxCtlError::BadColour(line) => write!(f, "bad colour: {line}"),
+            LpxCtlError::UnknownColour(name) => write!(f, "unknown colour: {name}"),
+            LpxCtlError::DuplicateName(name) => write!(f, "defined twice: {name}"),
+            LpxCtlError::BadPads(pads) => write!(f, "bad pads: {pads}"),
+            LpxCtlError::BadLayoutLine(n, line) => write!(f, "{n}: {line}"),
+            LpxCtlError::BadSection(n, section) => write!(f, "bad section {n}: {section}"),
+            LpxCtlError::BadTrack(n, track) => write!(f, "bad track {n}: {track}"),
+            LpxCtlError::BadButton(button) => write!(f, "bad button: {button}"),
+            LpxCtlError::BadControl(control) => write!(f, "bad control: {control}"),
+            LpxCtlError::BadChannel(channel) => write!(f, "bad channel: {channel}"),
+            LpxCtlError::BadBpm(bpm) => write!(f, "bad bpm, not 20-999: {bpm}"),
         }
     }
 }
@@ -8,54 +8,81 @@
 //! ["Programmer's Manual" ](https://fael-downloads-prod.focusrite.com/customer/prod/s3fs-public/downloads/Launchpad%20X%20-%20Programmers%20Reference%20Manual.pdf)
 extern crate midir;
 extern crate serde;
-// mod lpx_ctl_error;
+mod arpeggiator;
+mod buttons;
+mod cli;
+mod clock;
+mod colour;
+mod connections;
+mod config;
+mod controller;
+mod controls;
+mod device;
+mod editor;
+mod event;
+mod event_log;
+mod held_pads;
+mod hotplug;
+mod jack_midi;
+mod led;
+mod led_state;
+mod lpx_ctl_error;
+mod mod_host;
+mod scale;
 mod section;
+mod sequencer;
+mod show;
+mod simulator;
+mod text_layout;
+mod tui;
+mod velocity;
 
-use crate::midir::os::unix::VirtualOutput;
-use crate::section::Section;
+use crate::cli::{Cli, Command};
+use crate::config::Config;
+use crate::connections::{AlsaConnector, Connector};
+use crate::controller::{Controller, MidiSink, Outputs};
+use crate::device::Device;
+use crate::event::Event;
+use crate::event_log::{EventLog, LoggedSink};
+use crate::hotplug::LpxOutput;
+use crate::jack_midi::JackMidi;
+use crate::lpx_ctl_error::LpxCtlError;
+use crate::mod_host::{CommandSink, ModHost, ModHostConfig};
+use crate::midir::os::unix::{VirtualInput, VirtualOutput};
+use crate::scale::ScaleLayout;
+use crate::simulator::Simulator;
+use crate::tui::{MirrorSink, Tui, TuiState};
 use midir::{MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection};
+use std::cell::RefCell;
 use std::env;
 use std::error::Error;
 use std::fs::File;
 use std::io::prelude::*;
+use std::rc::Rc;
 use std::result::Result;
-use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-/// Initialise a vector of `Section` from a file.
-fn load_sections(filename: &str) -> Option<Vec<Section>> {
-    let mut file = match File::open(filename) {
-	Ok(f) => f,
-	Err(err) => panic!("{err}"),
-    };
-    let mut content = String::new();
-    match file.read_to_string(&mut content) {
-	Ok(_) => (),
-	Err(err) => panic!("{err}"),
-    };
+/// Initialise a `Config`, and its vector of `Section`, from a file.
+/// A `.txt` file is a text layout, the format `make_cfg` reads
+fn load_config(filename: &str) -> Result<Config, Box<dyn Error>> {
+    let load = || -> Result<Config, Box<dyn Error>> {
+        let mut content = String::new();
+        File::open(filename)?.read_to_string(&mut content)?;
 
-    // Create the sections from the file
-    let mut sections:Vec<Section> = Section::parse_json(&content).expect("Failed parsing JSON");
-    // If there is a default section with no pads put all unincluded pads in it
-    if let Some(index) = sections.iter().position(|x| x.pads.is_empty()) {
-
-	// Collect all pads mentioned so far
-	let mut pads_here: Vec<u8> = sections.iter().flat_map(|x| x.pads.clone()).collect();
-	if pads_here.len() < 64 {
-	    // Need the default
-	    pads_here.sort();
-	    // Check each row for missing pads and add them to default
-	    for r in 1..=8 {
-		let pads:Vec<&u8> = pads_here.iter().filter(|x| *x / 10 == r).collect();
-		for c in 1..=8  {
-		    let pad = r * 10 + c;
-		    if !pads.iter().any(|x| x == &&pad){
-			sections[index].pads.push(pad);
-		    }
-		}
-	    }
-	}
-    }
-    Some(sections)
+        // Create the sections from the file
+        if filename.ends_with(".txt") {
+            Ok(Config::parse_text(&content)?)
+        } else {
+            Config::parse_json(&content)
+        }
+    };
+    let mut config = load().map_err(|err| format!("{filename}: {err}"))?;
+    config.file = Some(filename.to_string());
+    Ok(config)
 }
 
 // Get a MIDI port that has a name containing `keyword`
@@ -76,150 +103,464 @@ fn get_midi_port<T: midir::MidiIO>(midi_io: &T, keyword: &str) -> Option<T::Port
 }
 
 /// Create an output MIDI port to the LPX.
-/// It uses the passed parameter `name` to create a prort: LpxCtl:<name>
-fn get_midi_out(name: &str) -> Result<MidiOutputConnection, Box<dyn Error>> {
-    let midi_output = MidiOutput::new("LpxCtl")?;
-    let port = get_midi_port(&midi_output, "Launchpad X LPX MIDI In").unwrap(); //.ok_or(Err("Failed guess port".into())?);
+/// `keyword` is part of the name of the device's port.
+/// It uses the passed parameter `name` to create a prort: <client>:<name>
+fn get_midi_out(
+    keyword: &str,
+    client: &str,
+    name: &str,
+) -> Result<MidiOutputConnection, Box<dyn Error>> {
+    let midi_output = MidiOutput::new(client)?;
+    let port = get_midi_port(&midi_output, keyword)
+        .ok_or_else(|| LpxCtlError::DeviceNotFound(keyword.to_string()))?;
     Ok(midi_output.connect(&port, name)?)
 }
 
 /// Create a MIDI input port, connected from the LPX MIDI port.
+/// `keyword` is part of the name of the device's port
+/// `client` is the client name for the created port
 /// `name` is the port name for the created port
 /// `f` is the function that takes a channel and sends the MIDI that
 /// it wants to handle down that channel
 /// `tx` is the channel
 fn get_midi_in(
+    keyword: &str,
+    client: &str,
     name: &str,
-    f: impl FnMut(u64, &[u8], &mut Sender<[u8; 3]>) + Send + 'static,
-    tx: Sender<[u8; 3]>,
-) -> Result<MidiInputConnection<Sender<[u8; 3]>>, Box<dyn Error>> {
-    let midi_input = MidiInput::new("LpxCtl")?;
-    let port = get_midi_port(&midi_input, "Launchpad X LPX MIDI In").unwrap();
-    //.ok_or(Err("Failed guess port".into())?);
+    f: impl FnMut(u64, &[u8], &mut Sender<Event>) + Send + 'static,
+    tx: Sender<Event>,
+) -> Result<MidiInputConnection<Sender<Event>>, Box<dyn Error>> {
+    let midi_input = MidiInput::new(client)?;
+    let port = get_midi_port(&midi_input, keyword)
+        .ok_or_else(|| LpxCtlError::DeviceNotFound(keyword.to_string()))?;
     let result = midi_input.connect(&port, name, f, tx)?;
     Ok(result)
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    // The only argument is a configuration file
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        panic!("Pass name of pad definitions, JSON formatted, file as sole argument");
+/// The MIDI input from the LPX.  Sends what it wants to handle to the
+/// main thread
+fn lpx_input(_stamp: u64, message: &[u8], tx: &mut Sender<Event>) {
+    // Notes and controls are three bytes.  Channel pressure is two.
+    // SysEx is the answer to asking the LPX's mode
+    if message.len() == 3 || message.len() == 2 || message.first() == Some(&240) {
+        tx.send(Event::Lpx(message.to_vec())).unwrap();
     }
-    let filename = &args[1];
+}
+
+/// The input and output connections to the LPX
+type LpxConnection = (MidiInputConnection<Sender<Event>>, MidiOutputConnection);
+
+/// Connect to the LPX, both ways.  `client` is the MIDI client name
+fn connect_lpx(
+    keyword: &str,
+    client: &str,
+    tx: Sender<Event>,
+) -> Result<LpxConnection, Box<dyn Error>> {
+    Ok((
+        get_midi_in(keyword, client, "read_input", lpx_input, tx)?,
+        get_midi_out(keyword, client, "colour_port")?,
+    ))
+}
+
+/// The note and control outputs
+struct MidiOutputs {
+    note: Box<dyn MidiSink>,
+    ctl: Box<dyn MidiSink>,
+
+    /// The JACK client, if JACK is configured.  Its ports are there as
+    /// long as it is.  (See `jack_midi.rs`)
+    jack: Option<Arc<JackMidi>>,
+}
+
+/// Establish the output that sends MIDI to whatever software will
+/// interpret the MIDI to create sound and MIDI controls to whatever
+/// interprets them.  An external programme will have to conmplete
+/// these setups as this programme does not know what they will be.
+/// They are JACK MIDI ports if JACK is configured
+///
+/// If `connections` is configured they are connected here, and
+/// reconnected when a destination reappears.  (See `connections.rs`)
+/// The ALSA clients are `<client>Note` and `<client>Ctl`
+fn open_outputs(config: &Config, client: &str) -> Result<MidiOutputs, Box<dyn Error>> {
+    let port_name = "port";
+    let connectors: Vec<Box<dyn Connector>>;
+    let result = match config.jack.as_ref() {
+        Some(jack_config) => {
+            let (jack, notes, controls) = JackMidi::new(jack_config)?;
+            let name = &jack_config.client_name;
+            eprintln!("2 JACK MIDI Output port '{name}:notes' is open");
+            eprintln!("3 JACK MIDI Output port '{name}:controls' is open");
+            connectors = jack.connectors(jack_config, &config.connections);
+            MidiOutputs {
+                note: Box::new(notes),
+                ctl: Box::new(controls),
+                jack: Some(jack),
+            }
+        }
+        None => {
+            let note_client = format!("{client}Note");
+            let (note, note_connector) =
+                AlsaConnector::new(&note_client, port_name, &config.connections.note)?;
 
-    // Initialise the collection of `Section` from the file. (See `section.rs`)
-    let sections: Vec<Section> = load_sections(filename).expect("Failed to load sections");
+            let ctl_client = format!("{client}Ctl");
+            let (ctl, ctl_connector) =
+                AlsaConnector::new(&ctl_client, port_name, &config.connections.ctl)?;
+            eprintln!("2 Virtual MIDI Output port '{note_client}:{port_name}' is open");
+            eprintln!("3 Virtual MIDI Output port '{ctl_client}:{port_name}' is open");
+            connectors = vec![Box::new(note_connector), Box::new(ctl_connector)];
+            MidiOutputs {
+                note: Box::new(note),
+                ctl: Box::new(ctl),
+                jack: None,
+            }
+        }
+    };
+    connections::keep_connected(connectors);
+    Ok(result)
+}
+
+/// Print the MIDI ports.  Those of the device are marked
+fn list_ports(client: &str, device: &dyn Device) -> Result<(), Box<dyn Error>> {
+    let keyword = device.port_keyword();
+    let midi_in = MidiInput::new(client)?;
+    let midi_out = MidiOutput::new(client)?;
+    let inputs = midi_in.ports().iter().map(|p| midi_in.port_name(p)).collect::<Vec<_>>();
+    let outputs = midi_out.ports().iter().map(|p| midi_out.port_name(p)).collect::<Vec<_>>();
+    for (title, names) in [("Inputs", inputs), ("Outputs", outputs)] {
+        println!("{title}:");
+        for name in names {
+            let name = name?;
+            let mark = if name.contains(keyword) { "*" } else { " " };
+            println!("{mark} {name}");
+        }
+    }
+    println!("(* {})", device.name());
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    // The command line.  (See `cli.rs`)
+    let cli = Cli::parse_args(env::args().collect());
+    let client_name = cli.client_name().to_string();
+    let (mut config, options) = match &cli.command {
+        Command::Run { file, options } => (load_config(file)?, options),
+        Command::Scale {
+            root,
+            scale,
+            row_offset,
+            json,
+            options,
+        } => {
+            // Generate a melodic layout.  (See `scale.rs`)
+            let args = [root.clone(), scale.clone(), row_offset.clone()];
+            let sections = ScaleLayout::from_args(&args)?.sections()?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&sections)?);
+                return Ok(());
+            }
+            (Config::from_sections(sections), options)
+        }
+        Command::Validate { file } => {
+            let config = load_config(file)?;
+            println!("{file}: {} sections", config.sections.len());
+            return Ok(());
+        }
+        Command::Show { file } => {
+            print!("{}", show::grid(&load_config(file)?.sections));
+            return Ok(());
+        }
+        Command::ListPorts => {
+            let device = cli.device.unwrap_or_default().device();
+            return list_ports(&client_name, device.as_ref());
+        }
+        Command::Convert { layout } => {
+            // Do what `make_cfg` does.  (See `text_layout.rs`)
+            let mut content = String::new();
+            File::open(layout)?.read_to_string(&mut content)?;
+            let sections =
+                text_layout::parse(&content).map_err(|err| format!("{layout}: {err}"))?;
+            println!("{}", serde_json::to_string_pretty(&sections)?);
+            return Ok(());
+        }
+        Command::Replay { log, file } => {
+            // Play a log back.  (See `event_log.rs`)
+            let mut content = String::new();
+            File::open(log)?.read_to_string(&mut content)?;
+            let entries = event_log::read(&content).map_err(|err| format!("{log}: {err}"))?;
+            let mut config = match file {
+                Some(filename) => load_config(filename)?,
+                None => Config::default(),
+            };
+            cli.apply(&mut config);
+            let mut outputs = open_outputs(&config, &client_name)?;
+            eprintln!("Connect the outputs then press enter to play {} messages", entries.len());
+            std::io::stdin().read_line(&mut String::new())?;
+            let result =
+                event_log::replay(&entries, outputs.note.as_mut(), outputs.ctl.as_mut());
+            if outputs.jack.is_some() {
+                // The JACK process thread sends MIDI in its next period
+                thread::sleep(Duration::from_millis(100));
+            }
+            return result;
+        }
+        Command::Simulate { file, script: script_file } => {
+            // Play a script of pad presses into a simulated LPX and
+            // print what is sent.  (See `simulator.rs`)
+            let mut config = load_config(file)?;
+            cli.apply(&mut config);
+            let mut script = String::new();
+            File::open(script_file)?.read_to_string(&mut script)?;
+            let recording = Simulator::new(config)?.run(&script)?;
+            print!("{recording}");
+
+            // What the LPX is showing at the end
+            let mut leds: Vec<_> = recording.leds().into_iter().collect();
+            leds.sort_by_key(|(pad, _)| *pad);
+            for (pad, led) in leds {
+                println!("{pad}: {led:?}");
+            }
+            return Ok(());
+        }
+    };
+    cli.apply(&mut config);
+    let tui = options.tui;
+
+    // The grid controller.  (See `device.rs`)
+    let device_kind = config.device;
+    let device = device_kind.device();
+    eprintln!("Device: {}", device.name());
 
     // The channel to send MIDI messages, received from the LPX in the
     // MidiInputConnection, here to the main thread
-    let (tx, rx): (Sender<[u8; 3]>, Receiver<[u8; 3]>) = mpsc::channel::<[u8; 3]>();
+    let (tx, rx): (Sender<Event>, Receiver<Event>) = mpsc::channel::<Event>();
 
-    // Connect to the LPX to receive pad press events.  `f` is the
-    // function that handles input MIDI and sends them back to themain
-    // thread
-    let f = move |_stamp, message: &[u8], tx: &mut Sender<[u8; 3]>| {
-        // let message = MidiMessage::from_bytes(message.to_vec());
-        if message.len() == 3 {
-            let m3: [u8; 3] = message.try_into().unwrap();
-            tx.send(m3).unwrap();
+    // Connect to the LPX to receive pad press events, and send it
+    // colour.  The input stays open as long as `lpx_in` holds it.
+    // The terminal mirror can be used without the LPX
+    let lpx_out = LpxOutput::default();
+    let mut lpx_in = match connect_lpx(device.port_keyword(), &client_name, tx.clone()) {
+        Ok((input, output)) => {
+            lpx_out.set(Some(output));
+            Some(input)
+        }
+        Err(err) if tui => {
+            eprintln!("{err}: Using the terminal only");
+            None
         }
+        Err(err) => return Err(err),
     };
-    // The port stays open as long as `_in` is in scope
-    let _in = get_midi_in("read_input", f, tx.clone())?;
+    let colour_port: Box<dyn MidiSink> = Box::new(lpx_out.clone());
 
-    // Create an output port to the LPX for sending it colour.
-    let mut colour_port: MidiOutputConnection = get_midi_out("colour_port")?;
+    // Follow the LPX being unplugged and plugged back in.  (See
+    // `hotplug.rs`)
+    let connected = Arc::new(AtomicBool::new(lpx_in.is_some()));
+    hotplug::watch(
+        device.port_keyword(),
+        &client_name,
+        connected.clone(),
+        tx.clone(),
+    )?;
 
-    // Selecting Layouts (page 7 programmers manual).  127 => "Programmer Mode"
-    let msg: [u8; 9] = [240, 0, 32, 41, 2, 12, 0, 127, 247];
-    match colour_port.send(&msg) {
-        Ok(()) => (),
-        Err(err) => eprintln!("{err}: Failed to send msg to LPX: {msg:?}"),
+    // The terminal mirror sees everything sent to the LPX.  (See
+    // `tui.rs`)
+    let tui_state = if tui {
+        Some(Arc::new(Mutex::new(TuiState::new(&config.sections))))
+    } else {
+        None
     };
+    let colour_port: Box<dyn MidiSink> = match tui_state.as_ref() {
+        Some(state) => Box::new(MirrorSink {
+            lpx: colour_port,
+            state: state.clone(),
+        }),
+        None => colour_port,
+    };
+
+    // The note and control outputs, logged if `--log` is given.  (See
+    // `event_log.rs`)
+    let port_name = "port";
+    let outputs = open_outputs(&config, &client_name)?;
+    let _jack = outputs.jack;
+    let log = match options.log.as_ref() {
+        Some(filename) => Some(Rc::new(RefCell::new(EventLog::create(filename)?))),
+        None => None,
+    };
+    let logged = |output: &'static str, sink: Box<dyn MidiSink>| -> Box<dyn MidiSink> {
+        match log.as_ref() {
+            Some(log) => Box::new(LoggedSink {
+                output,
+                sink,
+                log: log.clone(),
+            }),
+            None => sink,
+        }
+    };
+    let midi_note_out_port = logged("note", outputs.note);
+    let midi_ctl_out_port = logged("ctl", outputs.ctl);
 
-    let make_colour = |section: &Section, colour: [u8; 3]| -> Vec<u8> {
-        // Buid the MIDI command that sets the colours of all the pads
-        // in a section (they are all the same colour - part of what
-        // defines a section).  One long MIDI sysex message that sets
-        // many pads in one command
-
-        // "LED lighting SysEx message" programmer's mabual page 15
-        let mut colour_message: Vec<u8> = vec![240, 0, 32, 41, 2, 12, 3];
-        let pads: Vec<u8> = section.pads().to_vec();
-        for pad in pads.iter() {
-            colour_message.push(3); // RGB colour
-            colour_message.push(*pad); // Pad index
-            colour_message.extend(colour.to_vec()); // RGB tripple
-        }
-        colour_message.push(247); // End message
-        colour_message
+    // Optionally, a virtual input for a sequencer, or anything else,
+    // to send notes to.  They light the sections that play them.  The
+    // port stays open as long as `_feedback_in` is in scope
+    let _feedback_in: Option<MidiInputConnection<Sender<Event>>> = if config.feedback {
+        let feedback_client = format!("{client_name}Feedback");
+        let midi_in: MidiInput = MidiInput::new(&feedback_client)?;
+        let port = midi_in.create_virtual(
+            port_name,
+            |_stamp, message: &[u8], tx: &mut Sender<Event>| {
+                tx.send(Event::Feedback(message.to_vec())).unwrap();
+            },
+            tx.clone(),
+        )?;
+        eprintln!("4 Virtual MIDI Input port '{feedback_client}:{port_name}' is open");
+        Some(port)
+    } else {
+        None
     };
 
-    // Initialise the colours
-    for section in sections.iter() {
-        let colour = make_colour(section, section.main_colour);
-        match colour_port.send(&colour) {
-            Ok(()) => (),
-            Err(err) => eprintln!("{err}: Cannot send colour: {colour:?}"),
-        };
+    // MIDI clock output.  (See `clock.rs`)
+    let clock_out = config.clock.as_ref().map(|c| c.output).unwrap_or(false);
+    let midi_clock_out_port: Option<Box<dyn MidiSink>> = if clock_out {
+        let clock_client = format!("{client_name}ClockOut");
+        let midi_out: MidiOutput = MidiOutput::new(&clock_client)?;
+        let port = midi_out.create_virtual(port_name)?;
+        eprintln!("6 Virtual MIDI Output port '{clock_client}:{port_name}' is open");
+        Some(Box::new(port))
+    } else {
+        None
+    };
+    let clock_in = config.clock.as_ref().map(|c| c.input);
+
+    // mod-host, if any section sets plugin parameters.  (See
+    // `mod_host.rs`)
+    let mod_host_config = config.mod_host.clone().or_else(|| {
+        config
+            .sections
+            .iter()
+            .any(|s| !s.mod_host.is_empty())
+            .then(ModHostConfig::default)
+    });
+    let mod_host: Option<Box<dyn CommandSink>> = mod_host_config.map(|c| {
+        eprintln!("7 mod-host at {}", c.address);
+        Box::new(ModHost::new(&c)) as Box<dyn CommandSink>
+    });
+
+    // All the state is in the controller.  (See `controller.rs`)
+    let mut controller = Controller::new(
+        config,
+        Outputs {
+            lpx: colour_port,
+            note: midi_note_out_port,
+            ctl: midi_ctl_out_port,
+            clock: midi_clock_out_port,
+            mod_host,
+        },
+    );
+    if let Some(log) = log.as_ref() {
+        controller.log_to(log.clone());
     }
 
-    // Establish the output that sends MIDI to whatever software will
-    // interpret the MIDI to create sound and MIDI controls to
-    // whatever interprets them.  An external programme will have to
-    // conmplete these setups as this programme does not know what
-    // they will be
-    let midi_out: MidiOutput = MidiOutput::new("LpxCtlNote")?;
-    let port_name = "port";
-    let mut midi_note_out_port: MidiOutputConnection = midi_out.create_virtual(port_name)?;
+    // The clock input is for the configured clock, or for the
+    // sequencer to follow if there is no clock configured.  The port
+    // stays open as long as `_clock_in` is in scope
+    let clock_in = clock_in.unwrap_or(controller.sequencer_midi_clock());
+    let _clock_in: Option<MidiInputConnection<Sender<Event>>> =
+        if clock_in {
+            let clock_client = format!("{client_name}Clock");
+            let midi_in: MidiInput = MidiInput::new(&clock_client)?;
+            let port = midi_in.create_virtual(
+                port_name,
+                |_stamp, message: &[u8], tx: &mut Sender<Event>| {
+                    tx.send(Event::Clock(message.to_vec())).unwrap();
+                },
+                tx.clone(),
+            )?;
+            eprintln!("5 Virtual MIDI Input port '{clock_client}:{port_name}' is open");
+            Some(port)
+        } else {
+            None
+        };
 
-    let midi_out: MidiOutput = MidiOutput::new("LpxCtlCtl")?;
-    let port_name = "port";
-    let mut midi_ctl_out_port: MidiOutputConnection = midi_out.create_virtual(port_name)?;
-    eprintln!("2 Virtual MIDI Output port 'LpxCtlNote:{port_name}' is open");
-    eprintln!("3 Virtual MIDI Output port 'LpxCtlCtl:{port_name}' is open");
+    // The terminal is restored when `_tui` is dropped
+    let _tui = match tui_state.as_ref() {
+        Some(state) => Some(Tui::start(state.clone(), device_kind, tx.clone())?),
+        None => None,
+    };
+
+    // Stop on SIGINT and SIGTERM as if the user quit, so the LPX and
+    // the synthesisers are left as they were found
+    let quit_tx = tx.clone();
+    ctrlc::set_handler(move || {
+        let _ = quit_tx.send(Event::Quit);
+    })?;
+
+    // Programmer mode, and initialise the colours
+    controller.start(Instant::now())?;
 
     // Main loop.
-    loop {
-        let message: [u8; 3] = match rx.recv() {
-            Ok(m) => m,
-            Err(err) => panic!("{}", err),
-        };
-        if message[0] == 144 {
-            // All MIDI notes from LPX start with 144, for initial
-            // noteon and noteoff
-
-            // Find the section the pad is in
-            let pad: u8 = message[1];
-
-	    
-	    if let Some(section) = sections.iter().find(|x| x.pad_in(pad)){
-                // got the section for a pad
-
-                // Send out the note
-                let velocity = message[2];
-                let message: [u8; 3] = [message[0], section.midi_note, velocity];
-                midi_note_out_port.send(&message)?;
-
-                if velocity > 0 {
-                    // Note on
-                    // Set colour of section to "active_colour"
-                    let active_colour = make_colour(section, section.active_colour);
-                    colour_port.send(&active_colour).unwrap();
-                } else {
-                    // Note off
-                    // Restore the colour
-                    let main_colour = make_colour(section, section.main_colour);
-                    colour_port.send(&main_colour).unwrap();
+    let mut run = || -> Result<(), Box<dyn Error>> {
+        loop {
+            // Wake up when something is due
+            let received = match controller.next_due() {
+                Some(due) => {
+                    rx.recv_timeout(due.saturating_duration_since(Instant::now()))
                 }
-		continue;
-            }
-        } else if message[0] == 176 {
-            // A control signal
-            eprintln!("control_port On: Message{message:?}");
-            midi_ctl_out_port.send(&message).unwrap();
+                None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match received {
+                Ok(Event::Quit) => return Ok(()),
+                Ok(Event::LpxGone) => {
+                    eprintln!("{} has gone", device.name());
+                    lpx_in = None;
+                    lpx_out.set(None);
+                    connected.store(false, Ordering::SeqCst);
+                    controller.handle(Event::LpxGone, Instant::now())?
+                }
+                Ok(Event::LpxBack) if lpx_in.is_none() => {
+                    match connect_lpx(device.port_keyword(), &client_name, tx.clone()) {
+                        Ok((input, output)) => {
+                            eprintln!("{} is back", device.name());
+                            lpx_in = Some(input);
+                            lpx_out.set(Some(output));
+                            connected.store(true, Ordering::SeqCst);
+                            controller.handle(Event::LpxBack, Instant::now())?
+                        }
+                        // Tried again when it is next looked for
+                        Err(err) => eprintln!("{err}: Cannot reconnect"),
+                    }
+                }
+                // Already connected
+                Ok(Event::LpxBack) => (),
+                Ok(event) => {
+                    let lpx = matches!(event, Event::Lpx(_));
+                    if let (Some(state), Event::Lpx(m)) = (tui_state.as_ref(), &event) {
+                        // Pads held on the LPX are shown in the terminal
+                        if m.len() == 3 && m[0] & 0xF0 == 0x90 {
+                            if let Some(pad) = device.pad_from_note(m[1]) {
+                                state.lock().unwrap().note(pad, m[2]);
+                            }
+                        }
+                    }
+                    controller.handle(event, Instant::now())?;
+                    if let (Some(state), true) = (tui_state.as_ref(), lpx) {
+                        // The editor may have changed the sections
+                        state.lock().unwrap().set_sections(controller.sections());
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => controller.tick(Instant::now())?,
+                Err(err) => panic!("{}", err),
+            };
         }
+    };
+    let result = run();
+
+    // All notes off, the LEDs off, and the LPX back in the mode it was
+    // in.  Whether stopped by the user or an error
+    if let Err(err) = controller.stop(Instant::now()) {
+        eprintln!("{err}: Failed to stop cleanly");
+    }
+    if _jack.is_some() {
+        // The JACK process thread sends MIDI in its next period
+        thread::sleep(Duration::from_millis(100));
     }
-    // Ok(())
+    result
 }
@@ -0,0 +1,298 @@
+//! Setting LV2 plugin parameters in mod-host from the pads, as
+//! `lv2_ctl` does.  A section with `mod_host` actions sends
+//! `param_set <instance> <symbol> <value>` for each of them when it is
+//! pressed, over mod-host's TCP socket (`mod-host -p 5555`).  So pads
+//! can be effect switches, or snapshots of several parameters, with no
+//! MIDI learning
+//!
+//! An action with `off` set is a switch: A press sets `value`, unless
+//! `value` was the last set, then it sets `off`.  An action with
+//! `ramp_ms` moves the parameter from the last value set to the new
+//! one over that time, a step every `RAMP_STEP`.  The first time the
+//! parameter is set there is no last value, so it is set at once
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{self, prelude::*, BufReader};
+use std::net::TcpStream;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The time between the steps of a ramp
+pub const RAMP_STEP: Duration = Duration::from_millis(20);
+
+/// How long to wait for mod-host to answer a command
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn default_address() -> String {
+    "localhost:5555".to_string()
+}
+
+/// How to reach mod-host, from the configuration file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ModHostConfig {
+    /// `<host>:<port>` of mod-host's command socket
+    #[serde(default = "default_address")]
+    pub address: String,
+}
+
+impl Default for ModHostConfig {
+    fn default() -> Self {
+        Self {
+            address: default_address(),
+        }
+    }
+}
+
+/// A parameter set when a section is pressed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ParamAction {
+    /// The plugin's instance number in mod-host
+    pub instance: usize,
+
+    /// The LV2 port symbol.  E.g. "gain"
+    pub symbol: String,
+
+    pub value: f64,
+
+    /// If set the pad switches the parameter between `value` and this
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub off: Option<f64>,
+
+    /// If set the parameter moves to its new value over this many
+    /// milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ramp_ms: Option<u64>,
+}
+
+/// Somewhere to send mod-host commands
+pub trait CommandSink {
+    fn send(&mut self, command: &str) -> Result<(), Box<dyn Error>>;
+}
+
+/// A parameter: Instance number and symbol
+type Param = (usize, String);
+
+fn param_set(param: &Param, value: f64) -> String {
+    format!("param_set {} {} {value}", param.0, param.1)
+}
+
+/// A parameter moving from one value to another
+#[derive(Debug)]
+struct Ramp {
+    param: Param,
+    from: f64,
+    to: f64,
+    start: Instant,
+    duration: Duration,
+
+    /// When the next step is sent
+    next: Instant,
+}
+
+impl Ramp {
+    fn value(&self, now: Instant) -> f64 {
+        let done = now.saturating_duration_since(self.start).as_secs_f64()
+            / self.duration.as_secs_f64();
+        self.from + (self.to - self.from) * done.min(1.0)
+    }
+}
+
+/// The parameters set, and the ramps under way
+#[derive(Debug, Default)]
+pub struct Params {
+    /// The last value set for each parameter.  The end of its ramp,
+    /// if it is ramping
+    values: HashMap<Param, f64>,
+    ramps: Vec<Ramp>,
+}
+
+impl Params {
+    /// A section with `actions` is pressed.  The commands to send now
+    pub fn press(&mut self, actions: &[ParamAction], now: Instant) -> Vec<String> {
+        let mut result = vec![];
+        for action in actions.iter() {
+            let param: Param = (action.instance, action.symbol.clone());
+            let to = match action.off {
+                Some(off) if self.values.get(&param) == Some(&action.value) => off,
+                _ => action.value,
+            };
+
+            // From where it is now, which may be part way along a ramp
+            let from = match self.ramps.iter().position(|r| r.param == param) {
+                Some(index) => Some(self.ramps.remove(index).value(now)),
+                None => self.values.get(&param).copied(),
+            };
+            match (from, action.ramp_ms.filter(|ms| *ms > 0)) {
+                (Some(from), Some(ms)) => self.ramps.push(Ramp {
+                    param: param.clone(),
+                    from,
+                    to,
+                    start: now,
+                    duration: Duration::from_millis(ms),
+                    next: now + RAMP_STEP.min(Duration::from_millis(ms)),
+                }),
+                _ => result.push(param_set(&param, to)),
+            }
+            self.values.insert(param, to);
+        }
+        result
+    }
+
+    /// When the next step of a ramp is due
+    pub fn next_due(&self) -> Option<Instant> {
+        self.ramps.iter().map(|r| r.next).min()
+    }
+
+    /// The steps of ramps that are due by `now`.  The last step is
+    /// due at the end of the ramp, which is finished once it is sent
+    pub fn tick(&mut self, now: Instant) -> Vec<String> {
+        let mut result = vec![];
+        self.ramps.retain_mut(|ramp| {
+            if ramp.next > now {
+                return true;
+            }
+            result.push(param_set(&ramp.param, ramp.value(now)));
+            let end = ramp.start + ramp.duration;
+            ramp.next = (now + RAMP_STEP).min(end);
+            now < end
+        });
+        result
+    }
+}
+
+/// The connection to mod-host.  Commands are sent from a thread, one
+/// at a time, waiting for mod-host's answer to each.  If it can not
+/// be reached the command is dropped, and it is tried again for the
+/// next
+pub struct ModHost {
+    tx: Sender<String>,
+}
+
+impl ModHost {
+    pub fn new(config: &ModHostConfig) -> Self {
+        let (tx, rx) = channel();
+        let address = config.address.clone();
+        thread::spawn(move || Self::run(&address, rx));
+        Self { tx }
+    }
+
+    fn run(address: &str, rx: Receiver<String>) {
+        let mut connection: Option<(TcpStream, BufReader<TcpStream>)> = None;
+        while let Ok(command) = rx.recv() {
+            // Only the last value waiting for each parameter is sent
+            let mut commands = vec![command];
+            commands.extend(rx.try_iter());
+            let mut keep = vec![];
+            for command in commands.into_iter().rev() {
+                let key = command.rsplit_once(' ').map(|(k, _)| k.to_string());
+                if !keep.iter().any(|(k, _)| *k == key) {
+                    keep.push((key, command));
+                }
+            }
+
+            for (_, command) in keep.into_iter().rev() {
+                if connection.is_none() {
+                    match Self::connect(address) {
+                        Ok(c) => connection = Some(c),
+                        Err(err) => {
+                            eprintln!("{err}: Cannot connect to mod-host at {address}");
+                            continue;
+                        }
+                    }
+                }
+                let (stream, reader) = connection.as_mut().unwrap();
+                if let Err(err) = Self::exchange(stream, reader, &command) {
+                    eprintln!("{err}: mod-host did not take: {command}");
+                    connection = None;
+                }
+            }
+        }
+    }
+
+    fn connect(address: &str) -> io::Result<(TcpStream, BufReader<TcpStream>)> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+        let reader = BufReader::new(stream.try_clone()?);
+        Ok((stream, reader))
+    }
+
+    /// Send a command and read the answer.  Both end with a NUL.  The
+    /// answer is `resp <status>`, negative for an error
+    fn exchange(
+        stream: &mut TcpStream,
+        reader: &mut BufReader<TcpStream>,
+        command: &str,
+    ) -> io::Result<()> {
+        stream.write_all(command.as_bytes())?;
+        stream.write_all(&[0])?;
+        let mut response = vec![];
+        reader.read_until(0, &mut response)?;
+        let response = String::from_utf8_lossy(&response);
+        let response = response.trim_end_matches('\0');
+        if response.is_empty() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if response.starts_with("resp -") {
+            eprintln!("mod-host: {command}: {response}");
+        }
+        Ok(())
+    }
+}
+
+impl CommandSink for ModHost {
+    fn send(&mut self, command: &str) -> Result<(), Box<dyn Error>> {
+        Ok(self.tx.send(command.to_string())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn switch_and_ramp() {
+        let start = Instant::now();
+        let mut params = Params::default();
+        let bypass = ParamAction {
+            instance: 1,
+            symbol: "bypass".to_string(),
+            value: 1.0,
+            off: Some(0.0),
+            ramp_ms: None,
+        };
+        let actions = [bypass];
+        assert_eq!(params.press(&actions, start), vec!["param_set 1 bypass 1"]);
+        assert_eq!(params.press(&actions, start), vec!["param_set 1 bypass 0"]);
+        assert_eq!(params.press(&actions, start), vec!["param_set 1 bypass 1"]);
+
+        // The first time there is nothing to ramp from
+        let gain = |value| ParamAction {
+            instance: 0,
+            symbol: "gain".to_string(),
+            value,
+            off: None,
+            ramp_ms: Some(40),
+        };
+        assert_eq!(params.press(&[gain(0.0)], start), vec!["param_set 0 gain 0"]);
+        assert!(params.press(&[gain(1.0)], start).is_empty());
+        assert_eq!(params.next_due(), Some(start + RAMP_STEP));
+        assert_eq!(params.tick(start + RAMP_STEP), vec!["param_set 0 gain 0.5"]);
+        assert_eq!(params.tick(start + RAMP_STEP * 2), vec!["param_set 0 gain 1"]);
+        assert_eq!(params.next_due(), None);
+
+        // A tick after the end of a ramp, before its next step was
+        // due, still sends the last value
+        let mut gain = gain(0.0);
+        gain.ramp_ms = Some(50);
+        let start = start + RAMP_STEP * 3;
+        assert!(params.press(&[gain], start).is_empty());
+        assert_eq!(params.tick(start + RAMP_STEP).len(), 1);
+        assert_eq!(params.tick(start + RAMP_STEP * 2).len(), 1);
+        assert_eq!(params.next_due(), Some(start + Duration::from_millis(50)));
+        let after_end = start + Duration::from_millis(55);
+        assert_eq!(params.tick(after_end), vec!["param_set 0 gain 0"]);
+        assert_eq!(params.next_due(), None);
+    }
+}
@@ -0,0 +1,254 @@
+//! Generate melodic layouts: one section per pad, the note of each
+//! pad set by a root note, a scale, and how far apart the rows are.
+//! Root notes and notes in the scale are coloured so they stand out
+use crate::lpx_ctl_error::LpxCtlError;
+use crate::section::Section;
+use std::str::FromStr;
+
+/// The scales a layout can be built from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scale {
+    Major,
+    Minor,
+    Dorian,
+    Phrygian,
+    Lydian,
+    Mixolydian,
+    Locrian,
+    MajorPentatonic,
+    MinorPentatonic,
+    Chromatic,
+}
+
+impl Scale {
+    /// Semitones above the root of each note in the scale
+    pub fn intervals(&self) -> &'static [u8] {
+        match self {
+            Scale::Major => &[0, 2, 4, 5, 7, 9, 11],
+            Scale::Minor => &[0, 2, 3, 5, 7, 8, 10],
+            Scale::Dorian => &[0, 2, 3, 5, 7, 9, 10],
+            Scale::Phrygian => &[0, 1, 3, 5, 7, 8, 10],
+            Scale::Lydian => &[0, 2, 4, 6, 7, 9, 11],
+            Scale::Mixolydian => &[0, 2, 4, 5, 7, 9, 10],
+            Scale::Locrian => &[0, 1, 3, 5, 6, 8, 10],
+            Scale::MajorPentatonic => &[0, 2, 4, 7, 9],
+            Scale::MinorPentatonic => &[0, 3, 5, 7, 10],
+            Scale::Chromatic => &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+        }
+    }
+
+    /// Is `note` in this scale built on `root`
+    pub fn contains(&self, root: u8, note: u8) -> bool {
+        let interval = (note as i16 - root as i16).rem_euclid(12) as u8;
+        self.intervals().contains(&interval)
+    }
+}
+
+impl FromStr for Scale {
+    type Err = LpxCtlError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "major" | "ionian" => Scale::Major,
+            "minor" | "aeolian" => Scale::Minor,
+            "dorian" => Scale::Dorian,
+            "phrygian" => Scale::Phrygian,
+            "lydian" => Scale::Lydian,
+            "mixolydian" => Scale::Mixolydian,
+            "locrian" => Scale::Locrian,
+            "major_pentatonic" | "pentatonic" => Scale::MajorPentatonic,
+            "minor_pentatonic" => Scale::MinorPentatonic,
+            "chromatic" => Scale::Chromatic,
+            _ => return Err(LpxCtlError::UnknownScale(s.to_string())),
+        })
+    }
+}
+
+/// How the notes are laid out on the grid.  Moving one pad right
+/// always moves up one step
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RowOffset {
+    /// Every pad is one semitone above the pad to its left, and each
+    /// row is this many semitones above the row below.  Every shape
+    /// plays the same in any key.  Notes not in the scale are still
+    /// there, coloured differently
+    Isomorphic(u8),
+
+    /// Only notes in the scale are on the grid.  Each row is this
+    /// many scale degrees above the row below
+    InKey(u8),
+}
+
+impl FromStr for RowOffset {
+    type Err = LpxCtlError;
+
+    /// "fourths" is isomorphic, five semitones per row.  "thirds" is
+    /// four.  "isomorphic:<n>" is `n` semitones per row, and
+    /// "in_key:<n>" is `n` scale degrees.  "in_key" alone is three
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || LpxCtlError::UnknownScale(s.to_string());
+        let (kind, n) = match s.split_once(':') {
+            Some((kind, n)) => (kind, Some(n.parse::<u8>().map_err(|_| err())?)),
+            None => (s, None),
+        };
+        Ok(match (kind.to_lowercase().as_str(), n) {
+            ("fourths", None) => RowOffset::Isomorphic(5),
+            ("thirds", None) => RowOffset::Isomorphic(4),
+            ("isomorphic", n) => RowOffset::Isomorphic(n.unwrap_or(5)),
+            ("in_key", n) => RowOffset::InKey(n.unwrap_or(3)),
+            _ => return Err(err()),
+        })
+    }
+}
+
+/// The colours a generated layout uses
+#[derive(Debug, Clone, Copy)]
+pub struct ScaleColours {
+    pub root: [u8; 3],
+    pub in_scale: [u8; 3],
+    pub out_of_scale: [u8; 3],
+    pub active: [u8; 3],
+}
+
+impl Default for ScaleColours {
+    fn default() -> Self {
+        Self {
+            root: [0, 0, 127],
+            in_scale: [0, 60, 60],
+            out_of_scale: [0, 0, 0],
+            active: [0, 127, 0],
+        }
+    }
+}
+
+/// A description of a melodic layout
+#[derive(Debug, Clone, Copy)]
+pub struct ScaleLayout {
+    /// The MIDI note of the bottom left pad (11)
+    pub root: u8,
+    pub scale: Scale,
+    pub row_offset: RowOffset,
+    pub colours: ScaleColours,
+}
+
+impl ScaleLayout {
+    /// Build a layout from command line arguments:
+    /// <root> <scale> <row offset>
+    pub fn from_args(args: &[String]) -> Result<Self, LpxCtlError> {
+        let arg = |i: usize| {
+            args.get(i).ok_or_else(|| {
+                LpxCtlError::UnknownScale("<root> <scale> <row offset>".into())
+            })
+        };
+        let root = arg(0)?;
+        let root: u8 = root
+            .parse()
+            .ok()
+            .filter(|r| *r < 128)
+            .ok_or_else(|| LpxCtlError::UnknownScale(root.to_string()))?;
+        Ok(Self {
+            root,
+            scale: arg(1)?.parse()?,
+            row_offset: arg(2)?.parse()?,
+            colours: ScaleColours::default(),
+        })
+    }
+
+    /// The note for the pad `col` pads right and `row` pads up from
+    /// the bottom left
+    fn note(&self, row: u8, col: u8) -> u16 {
+        let root = self.root as u16;
+        match self.row_offset {
+            RowOffset::Isomorphic(n) => root + (row * n) as u16 + col as u16,
+            RowOffset::InKey(n) => {
+                let intervals = self.scale.intervals();
+                let degree = (row * n + col) as usize;
+                root + 12 * (degree / intervals.len()) as u16
+                    + intervals[degree % intervals.len()] as u16
+            }
+        }
+    }
+
+    /// Generate a section for each pad
+    pub fn sections(&self) -> Result<Vec<Section>, LpxCtlError> {
+        let mut result = vec![];
+        for row in 0..8 {
+            for col in 0..8 {
+                let pad = Section::row_col_to_pad(row + 1, col + 1);
+                let note = self.note(row, col);
+                if note > 127 {
+                    return Err(LpxCtlError::NoteOutOfRange(pad));
+                }
+                let note = note as u8;
+                let main_colour = if (note as i16 - self.root as i16)
+                    .rem_euclid(12)
+                    == 0
+                {
+                    self.colours.root
+                } else if self.scale.contains(self.root, note) {
+                    self.colours.in_scale
+                } else {
+                    self.colours.out_of_scale
+                };
+                result.push(Section::new(
+                    vec![pad],
+                    main_colour,
+                    self.colours.active,
+                    note,
+                ));
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fourths_layout() {
+        let layout = ScaleLayout {
+            root: 48,
+            scale: Scale::Major,
+            row_offset: "fourths".parse().unwrap(),
+            colours: ScaleColours::default(),
+        };
+        let sections = layout.sections().unwrap();
+        assert_eq!(sections.len(), 64);
+        assert!(Section::check_sections(&sections));
+        let note = |pad| sections.iter().find(|s| s.pad_in(pad)).unwrap();
+        assert_eq!(note(11).midi_note, 48);
+        assert_eq!(note(12).midi_note, 49);
+        assert_eq!(note(21).midi_note, 53);
+        assert_eq!(note(11).main_colour, layout.colours.root.into());
+        assert_eq!(note(13).main_colour, layout.colours.in_scale.into());
+        assert_eq!(note(12).main_colour, layout.colours.out_of_scale.into());
+    }
+
+    #[test]
+    fn in_key_layout() {
+        let layout = ScaleLayout {
+            root: 60,
+            scale: Scale::MinorPentatonic,
+            row_offset: "in_key".parse().unwrap(),
+            colours: ScaleColours::default(),
+        };
+        let sections = layout.sections().unwrap();
+        let note = |pad| sections.iter().find(|s| s.pad_in(pad)).unwrap();
+        assert_eq!(note(11).midi_note, 60);
+        assert_eq!(note(12).midi_note, 63);
+        assert_eq!(note(16).midi_note, 72);
+        assert_eq!(note(21).midi_note, 67);
+    }
+
+    #[test]
+    fn out_of_range() {
+        let layout = ScaleLayout::from_args(&[
+            "120".to_string(),
+            "chromatic".to_string(),
+            "fourths".to_string(),
+        ])
+        .unwrap();
+        assert!(layout.sections().is_err());
+    }
+}
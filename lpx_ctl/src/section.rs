@@ -1,15 +1,102 @@
+use crate::colour::Colour;
+use crate::controls::ControlConfig;
+use crate::led::Led;
+use crate::mod_host::ParamAction;
+use crate::velocity::VelocityConfig;
 use std::collections::HashSet;
-// use crate::lpx_ctl_error::LpxCtlError;
+use std::error::Error;
+use crate::lpx_ctl_error::LpxCtlError;
 use serde::{Deserialize, Serialize};
 /// A `Section` is a collection of pads on a LPX that is grouped".
-/// All the pads in it are one colour and emit the same note
+/// All the pads in it are one colour and emit the same note, or
+/// notes
 #[allow(unused)]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Section {
     pub pads: Vec<u8>, // 11-88
-    pub main_colour: [u8; 3],
-    pub active_colour: [u8; 3],
+    /// `[r, g, b]`, a palette index, or a name.  (See `colour.rs`)
+    pub main_colour: Colour,
+    pub active_colour: Colour,
     pub midi_note: u8,
+
+    /// Notes played along with `midi_note` to make a chord.  Empty
+    /// for a section that plays a single note
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chord: Vec<u8>,
+
+    /// If set the notes of the chord are played one after the other,
+    /// for as long as the pad is held, rather than all at once
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arpeggio: Option<Arpeggio>,
+
+    /// If true every pad pressed in the section plays the notes again.
+    /// Either way the notes start when the first pad is pressed and
+    /// stop when the last is released
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub polyphonic: bool,
+
+    /// Pressure on a pad (aftertouch) is sent as this control change,
+    /// rather than as polyphonic aftertouch for the section's notes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pressure_cc: Option<u8>,
+
+    /// If true the brightness of the active colour follows the
+    /// pressure on the pads
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pressure_colour: bool,
+
+    /// Shown instead of `main_colour`, or `active_colour`, if set.
+    /// For flashing and pulsing in time with the clock
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub main_led: Option<Led>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_led: Option<Led>,
+
+    /// How the velocity of a press is changed.  If not set the
+    /// configuration's `velocity` is used.  (See `velocity.rs`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub velocity: Option<VelocityConfig>,
+
+    /// LV2 plugin parameters set in mod-host when the section is
+    /// pressed.  (See `mod_host.rs`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mod_host: Vec<ParamAction>,
+
+    /// Starting a section stops the other sections with the same
+    /// choke group, as if their pads were released.  E.g. a closed
+    /// hi-hat stopping an open one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub choke_group: Option<u8>,
+
+    /// If true a press turns the section on, and it stays on until the
+    /// next press, or until another section in its choke group starts.
+    /// So only one section in a choke group is on: A radio group
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub radio: bool,
+
+    /// If set the section is a fader, or an XY pad, that sends
+    /// control changes, not notes.  (See `controls.rs`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub control: Option<ControlConfig>,
+}
+
+/// The order notes of an arpeggio are played in
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ArpeggioOrder {
+    #[default]
+    Up,
+    Down,
+    UpDown,
+}
+
+/// How to play the notes of a section as an arpeggio
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Arpeggio {
+    /// Milliseconds each note sounds for
+    pub step_ms: u64,
+    #[serde(default)]
+    pub order: ArpeggioOrder,
 }
 
 impl Section {
@@ -24,9 +111,21 @@ impl Section {
         // -> Result<Self, LpxCtlError>
         let result = Self {
             pads,
-            main_colour,
-            active_colour,
+            main_colour: main_colour.into(),
+            active_colour: active_colour.into(),
             midi_note,
+            chord: vec![],
+            arpeggio: None,
+            polyphonic: false,
+            pressure_cc: None,
+            pressure_colour: false,
+            main_led: None,
+            active_led: None,
+            velocity: None,
+            mod_host: vec![],
+            choke_group: None,
+            radio: false,
+            control: None,
         };
         if result.valid() {
             // Ok(result)
@@ -41,6 +140,7 @@ impl Section {
     /// Each pad in `pads` must be valid
     /// There must be no repeats
     /// There can be zero pads
+    /// Every note must be a valid MIDI note
     fn valid(&self) -> bool {
         !self.pads.iter().any(|x| {
             !Self::valid_pad(*x)
@@ -52,19 +152,36 @@ impl Section {
                     .len()
                     != 1
         }) && self.pads.len() <= 64
+            && self.notes().iter().all(|n| *n < 128)
+    }
+
+    /// Check each section on its own, as `valid` does.  The error
+    /// names the first that is not
+    pub fn check_each(sections: &[Section]) -> Result<(), LpxCtlError> {
+        match sections.iter().position(|s| !s.valid()) {
+            Some(index) => Err(LpxCtlError::BadSection(
+                index,
+                format!(
+                    "pads {:?}, notes {:?}",
+                    sections[index].pads,
+                    sections[index].notes()
+                ),
+            )),
+            None => Ok(()),
+        }
     }
 
     // Check that a `pad` is valid
-    fn valid_pad(pad: u8) -> bool {
-        (11..=88).contains(&pad) && pad % 10 != 0 && pad % 10 != 9
+    pub fn valid_pad(pad: u8) -> bool {
+        (11..=88).contains(&pad) && !pad.is_multiple_of(10) && pad % 10 != 9
     }
 
     // Check a set of `Section` to see if they are valid as a grouop
-    pub fn check_sections(sections: &Vec<Section>) -> bool {
+    pub fn check_sections(sections: &[Section]) -> bool {
         // Can only be one section with no pads.  It is the default section
         let default_section_count = sections
             .iter()
-            .filter(|x| x.pads.len() == 0)
+            .filter(|x| x.pads.is_empty())
             .collect::<Vec<&Section>>()
             .len();
 	let a = if default_section_count < 2 {
@@ -75,7 +192,7 @@ impl Section {
 	};
         // No intersections
         let b = true;
-        for i in 0..(sections.len() - 1) {
+        for i in 0..sections.len().saturating_sub(1) {
             for j in (i + 1)..sections.len() {
                 if sections[i].intersect(&sections[j]) {
                     eprintln!(
@@ -116,21 +233,15 @@ impl Section {
                 }
             }
         }
-        return false;
+        false
     }
 
-    pub fn parse_json(input: &str) -> Option<Vec<Section>>{
-	//eprintln!("Parse:{input}");
-        let result: Vec<Section> = match 
-	    serde_json::from_str(input){
-		Ok(r) => r,
-		Err(err) => panic!("{err}"),
-	    };
+    pub fn parse_json(input: &str) -> Result<Vec<Section>, Box<dyn Error>> {
+        let result: Vec<Section> = serde_json::from_str(input)?;
+        Self::check_each(&result)?;
         match Self::check_sections(&result) {
-            true => Some(result),
-            false => {
-		panic!("Sections check failed")
-	    },
+            true => Ok(result),
+            false => Err(LpxCtlError::BadPads("Sections check failed".to_string()).into()),
         }
     }
     pub fn row_col_to_pad(row: u8, col: u8) -> u8{
@@ -151,6 +262,53 @@ impl Section {
     pub fn pads(&self) -> &Vec<u8> {
         &self.pads
     }
+
+    /// All the notes this section plays: `midi_note` first then the
+    /// rest of the chord
+    pub fn notes(&self) -> Vec<u8> {
+        let mut notes = vec![self.midi_note];
+        notes.extend(self.chord.iter());
+        notes
+    }
+
+    /// What the pads show when the section is not active
+    pub fn main_led(&self) -> Led {
+        self.main_led.unwrap_or(self.main_colour.led())
+    }
+
+    /// What the pads show when the section is active
+    pub fn active_led(&self) -> Led {
+        self.active_led.unwrap_or(self.active_colour.led())
+    }
+
+    /// The active colour at a brightness of `level` (0-127).  A
+    /// palette colour can not be scaled, so is unchanged
+    pub fn scaled_active_led(&self, level: u8) -> Led {
+        match self.active_colour.rgb() {
+            Some(colour) => Self::scale_colour(colour, level).into(),
+            None => self.active_colour.led(),
+        }
+    }
+
+    /// The MIDI to send for `pressure` on a pad in this section, on
+    /// `channel`
+    pub fn pressure_messages(&self, channel: u8, pressure: u8) -> Vec<[u8; 3]> {
+        match self.pressure_cc {
+            Some(cc) => vec![[0xB0 | channel, cc, pressure]],
+            None => self
+                .notes()
+                .iter()
+                .map(|note| [0xA0 | channel, *note, pressure])
+                .collect(),
+        }
+    }
+
+    /// Scale `colour` by `level` (0-127).  It never goes below a
+    /// quarter brightness so a lightly held pad is still lit
+    pub fn scale_colour(colour: [u8; 3], level: u8) -> [u8; 3] {
+        let level = 32 + level.min(127) as u16 * 95 / 127;
+        colour.map(|c| (c as u16 * level / 127) as u8)
+    }
 }
 
 use std::fmt;
@@ -159,14 +317,33 @@ impl fmt::Display for Section {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "Section - Pads: {:?}  Main Colour:[{}, {}, {}], Active Colour: [{}, {}, {}]",
-            self.pads,
-            self.main_colour[0],
-            self.main_colour[1],
-            self.main_colour[2],
-            self.active_colour[0],
-            self.active_colour[1],
-            self.active_colour[2]
+            "Section - Pads: {:?}  Main Colour:{:?}, Active Colour: {:?}",
+            self.pads, self.main_colour, self.active_colour,
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_each_section() {
+        let section = |pads: &str, chord: &str| {
+            format!(
+                r#"{{"pads": {pads}, "main_colour": [1, 0, 0], "active_colour": [0, 1, 0],
+                    "midi_note": 60, "chord": {chord}}}"#
+            )
+        };
+        let sections = |second: String| format!("[{}, {second}]", section("[11]", "[64]"));
+        assert!(Section::parse_json(&sections(section("[]", "[67]"))).is_ok());
+        for bad in [
+            section("[]", "[200]"),
+            section("[12, 12]", "[]"),
+            section("[19]", "[]"),
+        ] {
+            let err = Section::parse_json(&sections(bad)).unwrap_err();
+            assert!(err.to_string().starts_with("bad section 1: "), "{err}");
+        }
+    }
+}
@@ -0,0 +1,369 @@
+//! A step sequencer.  In sequencer mode the grid shows eight steps
+//! (columns) of eight tracks (rows, the top row is the first track).
+//! Pressing a pad turns a step on or off.  Each track plays the note
+//! of a section, in the order the sections are defined, and is
+//! coloured like it.  The column being played is lit up
+//!
+//! The buttons: "Capture MIDI" (CC 98) enters and leaves sequencer
+//! mode, "Volume" (CC 89) starts and stops playing, and "Record Arm"
+//! (CC 19) saves the pattern
+use crate::clock;
+use crate::led::Led;
+use crate::lpx_ctl_error::LpxCtlError;
+use crate::section::Section;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::File;
+use std::io::prelude::*;
+use std::time::{Duration, Instant};
+
+/// Number of steps in a pattern, and tracks
+pub const STEPS: usize = 8;
+pub const TRACKS: usize = 8;
+
+/// The control buttons the sequencer uses
+pub const MODE_CC: u8 = 98;
+pub const PLAY_CC: u8 = 89;
+pub const SAVE_CC: u8 = 19;
+
+/// Steps in a beat.  Each step is a sixteenth note
+const STEPS_PER_BEAT: u32 = 4;
+
+const PULSES_PER_STEP: u32 = clock::PULSES_PER_BEAT / STEPS_PER_BEAT;
+
+/// Colour of the column being played, where no step is on
+const PLAYHEAD_COLOUR: [u8; 3] = [20, 20, 20];
+
+fn default_bpm() -> f64 {
+    120.0
+}
+
+/// How the sequencer is set up, from the configuration file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SequencerConfig {
+    /// Tempo when using the internal clock
+    #[serde(default = "default_bpm")]
+    pub bpm: f64,
+
+    /// If true follow MIDI clock, start, and stop instead of the
+    /// sequencer's own clock.  The clock received on the virtual input
+    /// `LpxCtlClock:port`, or generated if `clock` is configured.
+    /// (See `clock.rs`)
+    #[serde(default)]
+    pub midi_clock: bool,
+
+    /// The file the pattern is loaded from and saved to
+    #[serde(default)]
+    pub pattern: Option<String>,
+}
+
+/// One row of the sequencer
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Track {
+    pub note: u8,
+    pub velocity: u8,
+    pub steps: [bool; STEPS],
+
+    #[serde(skip)]
+    main_led: Led,
+    #[serde(skip)]
+    active_led: Led,
+}
+
+/// What is saved and loaded
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Pattern {
+    pub tracks: Vec<Track>,
+}
+
+impl SequencerConfig {
+    /// Check the tempo is in range
+    pub fn check(&self) -> Result<(), LpxCtlError> {
+        if !clock::BPM.contains(&self.bpm) {
+            return Err(LpxCtlError::BadBpm(self.bpm));
+        }
+        Ok(())
+    }
+}
+
+impl Pattern {
+    /// An empty pattern with a track for each of the first `TRACKS`
+    /// sections
+    fn from_sections(sections: &[Section]) -> Self {
+        Self {
+            tracks: sections
+                .iter()
+                .take(TRACKS)
+                .map(|s| Track {
+                    note: s.midi_note,
+                    velocity: 100,
+                    steps: [false; STEPS],
+                    main_led: s.main_led(),
+                    active_led: s.active_led(),
+                })
+                .collect(),
+        }
+    }
+
+    fn load(filename: &str) -> Result<Self, Box<dyn Error>> {
+        let mut content = String::new();
+        File::open(filename)?.read_to_string(&mut content)?;
+        Self::parse(&content)
+    }
+
+    /// A pattern from JSON.  Every note and velocity must be 0-127
+    fn parse(input: &str) -> Result<Self, Box<dyn Error>> {
+        let pattern: Self = serde_json::from_str(input)?;
+        for (index, track) in pattern.tracks.iter().enumerate() {
+            if track.note > 127 || track.velocity > 127 {
+                let track = format!("note {}, velocity {}", track.note, track.velocity);
+                return Err(LpxCtlError::BadTrack(index, track).into());
+            }
+        }
+        Ok(pattern)
+    }
+}
+
+#[derive(Debug)]
+pub struct Sequencer {
+    config: SequencerConfig,
+    pub pattern: Pattern,
+
+    /// The step that will be played next
+    position: usize,
+
+    /// The notes sounding now.  They are turned off when the next
+    /// step is played, or the sequencer stops
+    sounding: Vec<u8>,
+
+    playing: bool,
+
+    /// When the next step is due, with the internal clock
+    due: Option<Instant>,
+
+    /// MIDI clock pulses since the last step
+    pulses: u32,
+}
+
+impl Sequencer {
+    /// Make a sequencer for `sections`.  If the pattern file can be
+    /// read use it, otherwise start with an empty pattern
+    pub fn new(config: &SequencerConfig, sections: &[Section]) -> Self {
+        let mut pattern = Pattern::from_sections(sections);
+        if let Some(filename) = config.pattern.as_ref() {
+            match Pattern::load(filename) {
+                Ok(loaded) => {
+                    // Colours come from the sections, in order
+                    let colours: Vec<(Led, Led)> = pattern
+                        .tracks
+                        .iter()
+                        .map(|t| (t.main_led, t.active_led))
+                        .collect();
+                    pattern = loaded;
+                    pattern.tracks.truncate(TRACKS);
+                    for (track, colours) in
+                        pattern.tracks.iter_mut().zip(colours.iter())
+                    {
+                        (track.main_led, track.active_led) = *colours;
+                    }
+                }
+                Err(err) => eprintln!("{err}: Cannot load pattern {filename}"),
+            }
+        }
+        Self {
+            config: config.clone(),
+            pattern,
+            position: 0,
+            sounding: vec![],
+            playing: false,
+            due: None,
+            pulses: 0,
+        }
+    }
+
+    /// Write the pattern to its file
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(filename) = self.config.pattern.as_ref() {
+            let mut file = File::create(filename)?;
+            file.write_all(serde_json::to_string_pretty(&self.pattern)?.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Does the sequencer follow MIDI clock
+    pub fn midi_clock(&self) -> bool {
+        self.config.midi_clock
+    }
+
+    fn step_length(&self) -> Duration {
+        Duration::from_secs_f64(60.0 / self.config.bpm.max(1.0) / STEPS_PER_BEAT as f64)
+    }
+
+    /// The track and step for a pad
+    fn pad_to_step(pad: u8) -> Option<(usize, usize)> {
+        let track = TRACKS.checked_sub(Section::pad_to_row(pad) as usize)?;
+        let step = (Section::pad_to_col(pad) as usize).checked_sub(1)?;
+        if step < STEPS {
+            Some((track, step))
+        } else {
+            None
+        }
+    }
+
+    /// Turn on, or off, the step for `pad`
+    pub fn toggle(&mut self, pad: u8) {
+        if let Some((track, step)) = Self::pad_to_step(pad) {
+            if let Some(track) = self.pattern.tracks.get_mut(track) {
+                track.steps[step] = !track.steps[step];
+            }
+        }
+    }
+
+    /// Start playing from the first step.  With the internal clock the
+    /// first step is due now
+    pub fn start(&mut self, now: Instant) {
+        self.playing = true;
+        self.position = 0;
+        self.pulses = 0;
+        if !self.config.midi_clock {
+            self.due = Some(now);
+        }
+    }
+
+    /// Stop playing.  Returns note-offs for the notes sounding
+    pub fn stop(&mut self) -> Vec<[u8; 3]> {
+        self.playing = false;
+        self.due = None;
+        self.sounding.drain(..).map(|n| [144, n, 0]).collect()
+    }
+
+    pub fn playing(&self) -> bool {
+        self.playing
+    }
+
+    /// The step that will be played next.  When it changes the
+    /// display needs updating
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Play the next step: note-offs for the last then note-ons
+    fn step(&mut self) -> Vec<[u8; 3]> {
+        let mut result: Vec<[u8; 3]> =
+            self.sounding.drain(..).map(|n| [144, n, 0]).collect();
+        for track in self.pattern.tracks.iter() {
+            if track.steps[self.position] {
+                result.push([144, track.note, track.velocity]);
+                self.sounding.push(track.note);
+            }
+        }
+        self.position = (self.position + 1) % STEPS;
+        result
+    }
+
+    /// With the internal clock play the steps that are due by `now`
+    pub fn tick(&mut self, now: Instant) -> Vec<[u8; 3]> {
+        let mut result = vec![];
+        while let Some(due) = self.due {
+            if due > now {
+                break;
+            }
+            result.extend(self.step());
+            self.due = Some(due + self.step_length());
+        }
+        result
+    }
+
+    /// When the next step is due, using the internal clock
+    pub fn next_due(&self) -> Option<Instant> {
+        self.due
+    }
+
+    /// Handle a real time message from the MIDI clock input:
+    /// clock, start, continue, stop
+    pub fn clock(&mut self, message: u8, now: Instant) -> Vec<[u8; 3]> {
+        match message {
+            clock::CLOCK if self.playing => {
+                // Clock pulse.  A step is played on the first pulse
+                // after start and every `PULSES_PER_STEP` after that
+                let mut result = vec![];
+                if self.pulses.is_multiple_of(PULSES_PER_STEP) {
+                    result = self.step();
+                }
+                self.pulses += 1;
+                result
+            }
+            clock::START => {
+                self.start(now);
+                vec![]
+            }
+            clock::CONTINUE => {
+                self.playing = true;
+                vec![]
+            }
+            clock::STOP => self.stop(),
+            _ => vec![],
+        }
+    }
+
+    /// The colour of every pad on the grid
+    pub fn leds(&self) -> Vec<(u8, Led)> {
+        // The step that is sounding is the one before `position`
+        let playhead = (self.position + STEPS - 1) % STEPS;
+        let mut result = vec![];
+        for track in 0..TRACKS {
+            for step in 0..STEPS {
+                let pad = Section::row_col_to_pad((TRACKS - track) as u8, step as u8 + 1);
+                let led = match self.pattern.tracks.get(track) {
+                    Some(t) if t.steps[step] && self.playing && step == playhead => {
+                        t.active_led
+                    }
+                    Some(t) if t.steps[step] => t.main_led,
+                    _ if self.playing && step == playhead => PLAYHEAD_COLOUR.into(),
+                    _ => Led::default(),
+                };
+                result.push((pad, led));
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn play_steps() {
+        let sections = vec![
+            Section::new(vec![11], [1, 1, 1], [2, 2, 2], 36),
+            Section::new(vec![12], [3, 3, 3], [4, 4, 4], 38),
+        ];
+        let config: SequencerConfig = serde_json::from_str("{}").unwrap();
+        assert!(config.check().is_ok());
+        for bpm in ["1e12", "0", "-120"] {
+            let bad: SequencerConfig =
+                serde_json::from_str(&format!(r#"{{"bpm": {bpm}}}"#)).unwrap();
+            assert!(bad.check().is_err());
+        }
+        let mut sequencer = Sequencer::new(&config, &sections);
+
+        // First track is the top row, second the row below
+        sequencer.toggle(81);
+        sequencer.toggle(72);
+        let now = Instant::now();
+        sequencer.start(now);
+        assert_eq!(sequencer.tick(now), vec![[144, 36, 100]]);
+        let now = now + sequencer.step_length();
+        assert_eq!(sequencer.tick(now), vec![[144, 36, 0], [144, 38, 100]]);
+        assert!(sequencer.leds().contains(&(72, [4, 4, 4].into())));
+        assert!(sequencer.leds().contains(&(81, [1, 1, 1].into())));
+        assert_eq!(sequencer.stop(), vec![[144, 38, 0]]);
+
+        let steps = "[false, false, false, false, false, false, false, false]";
+        let track = |note| format!(r#"{{"note": {note}, "velocity": 100, "steps": {steps}}}"#);
+        let pattern = |note| format!(r#"{{"tracks": [{}, {}]}}"#, track(36), track(note));
+        assert!(Pattern::parse(&pattern(38)).is_ok());
+        assert!(Pattern::parse(&pattern(200)).is_err());
+    }
+}
@@ -0,0 +1,92 @@
+//! The sections of a layout as a grid of text, for `lpx_ctl show`.
+//! Each pad shows the number of its section, the top row first as
+//! on the LPX.  Then what each section plays
+use crate::controls::ControlKind;
+use crate::section::Section;
+use std::fmt::Write;
+
+const NOTE_NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+
+/// The name of a MIDI note.  60 is C4
+pub fn note_name(note: u8) -> String {
+    format!("{}{}", NOTE_NAMES[note as usize % 12], note as i16 / 12 - 1)
+}
+
+/// What a section plays
+fn describe(section: &Section) -> String {
+    match section.control.as_ref().map(|c| &c.kind) {
+        Some(ControlKind::Fader { cc }) => format!("fader, CC {cc}"),
+        Some(ControlKind::Xy { x_cc, y_cc }) => format!("XY pad, CC {x_cc} and {y_cc}"),
+        None => {
+            let notes: Vec<String> = section.notes().into_iter().map(note_name).collect();
+            let mut result = notes.join(" ");
+            if section.arpeggio.is_some() {
+                result += ", arpeggio";
+            }
+            if section.polyphonic {
+                result += ", polyphonic";
+            }
+            if section.radio {
+                result += ", radio";
+            }
+            if let Some(group) = section.choke_group {
+                result += &format!(", choke group {group}");
+            }
+            result
+        }
+    }
+}
+
+/// The grid, and a line for each section
+pub fn grid(sections: &[Section]) -> String {
+    let mut result = String::new();
+    for row in (1..=8).rev() {
+        write!(result, "{row} ").unwrap();
+        for col in 1..=8 {
+            let pad = Section::row_col_to_pad(row, col);
+            match sections.iter().position(|s| s.pad_in(pad)) {
+                Some(index) => write!(result, "{index:>3}").unwrap(),
+                None => result += "  .",
+            }
+        }
+        result += "\n";
+    }
+    result += "  ";
+    for col in 1..=8 {
+        write!(result, "{col:>3}").unwrap();
+    }
+    result += "\n\n";
+    for (index, section) in sections.iter().enumerate() {
+        writeln!(
+            result,
+            "{index:>3}: {}  ({} pads, {} / {})",
+            describe(section),
+            section.pads.len(),
+            serde_json::to_string(&section.main_colour).unwrap_or_default(),
+            serde_json::to_string(&section.active_colour).unwrap_or_default()
+        )
+        .unwrap();
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn show_sections() {
+        let mut sections = vec![
+            Section::new(vec![81, 82], [1, 0, 0], [0, 1, 0], 60),
+            Section::new(vec![11], [1, 0, 0], [0, 1, 0], 61),
+        ];
+        sections[1].chord = vec![65];
+        let grid = grid(&sections);
+        let lines: Vec<&str> = grid.lines().collect();
+        assert_eq!(lines[0], "8   0  0  .  .  .  .  .  .");
+        assert_eq!(lines[7], "1   1  .  .  .  .  .  .  .");
+        assert_eq!(lines[8], "    1  2  3  4  5  6  7  8");
+        assert!(lines[11].starts_with("  1: C#4 F4  (1 pads"));
+        assert_eq!(note_name(0), "C-1");
+    }
+}
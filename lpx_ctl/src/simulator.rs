@@ -0,0 +1,501 @@
+//! Run lpx_ctl without an LPX.  A script of pad presses, and other
+//! input, is played into a `Controller` and everything it sends is
+//! recorded.  Time is simulated, so arpeggios, the sequencer, and
+//! the clock run as fast as the script allows
+//!
+//! A script has one command per line.  Blank lines and lines
+//! starting with `#` are ignored
+//! * press <pad> [velocity]  Default velocity 127
+//! * release <pad>
+//! * pressure <pad> <value>  Polyphonic aftertouch
+//! * control <cc> <value>    A button on the top or side
+//! * feedback <note> <velocity>  A note on the feedback input
+//! * clock | start | continue | stop  On the clock input
+//! * unplug | replug  The LPX is unplugged, or plugged back in
+//! * quit  Finish, as `lpx_ctl` does on exit
+//! * wait <milliseconds>
+//!
+//! Commands sent to mod-host are recorded as text
+use crate::clock;
+use crate::config::Config;
+use crate::controller::{Controller, MidiSink, Outputs};
+use crate::event::Event;
+use crate::led::Led;
+use crate::led_state;
+use crate::mod_host::CommandSink;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// What was sent to one output: When (milliseconds from the start)
+/// and the MIDI
+pub type Log = Vec<(u64, Vec<u8>)>;
+
+/// Everything the controller sent
+#[derive(Debug, Clone, Default)]
+pub struct Recording {
+    pub lpx: Log,
+    pub note: Log,
+    pub ctl: Log,
+    pub clock: Log,
+    pub mod_host: Log,
+}
+
+impl Recording {
+    /// What each pad is showing, from the "LED lighting SysEx
+    /// messages" sent to the LPX
+    pub fn leds(&self) -> HashMap<u8, Led> {
+        self.lpx
+            .iter()
+            .flat_map(|(_, message)| Led::from_sysex(message))
+            .collect()
+    }
+}
+
+impl fmt::Display for Recording {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut all: Vec<(u64, &str, &Vec<u8>)> = vec![];
+        for (name, log) in [
+            ("lpx", &self.lpx),
+            ("note", &self.note),
+            ("ctl", &self.ctl),
+            ("clock", &self.clock),
+            ("mod_host", &self.mod_host),
+        ] {
+            all.extend(log.iter().map(|(t, m)| (*t, name, m)));
+        }
+        // Stable, so messages at the same time stay in output order
+        all.sort_by_key(|(t, _, _)| *t);
+        for (t, name, message) in all {
+            if name == "mod_host" {
+                writeln!(f, "{t} {name} {}", String::from_utf8_lossy(message))?;
+            } else {
+                writeln!(f, "{t} {name} {message:?}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An output that records what is sent to it.  MIDI, or mod-host
+/// commands
+struct RecordingSink {
+    log: Rc<RefCell<Recording>>,
+    select: fn(&mut Recording) -> &mut Log,
+    elapsed: Rc<Cell<Duration>>,
+}
+
+impl MidiSink for RecordingSink {
+    fn send(&mut self, message: &[u8]) -> Result<(), Box<dyn Error>> {
+        let t = self.elapsed.get().as_millis() as u64;
+        (self.select)(&mut self.log.borrow_mut()).push((t, message.to_vec()));
+        Ok(())
+    }
+}
+
+impl CommandSink for RecordingSink {
+    fn send(&mut self, command: &str) -> Result<(), Box<dyn Error>> {
+        MidiSink::send(self, command.as_bytes())
+    }
+}
+
+pub struct Simulator {
+    controller: Controller,
+    recording: Rc<RefCell<Recording>>,
+
+    /// Simulated time since the start
+    elapsed: Rc<Cell<Duration>>,
+    start: Instant,
+}
+
+impl Simulator {
+    /// Make a controller with simulated outputs and start it
+    pub fn new(config: Config) -> Result<Self, Box<dyn Error>> {
+        let recording = Rc::new(RefCell::new(Recording::default()));
+        let elapsed = Rc::new(Cell::new(Duration::ZERO));
+        let sink = |select: fn(&mut Recording) -> &mut Log| -> Box<dyn MidiSink> {
+            Box::new(RecordingSink {
+                log: recording.clone(),
+                select,
+                elapsed: elapsed.clone(),
+            })
+        };
+        let clock_out = config.clock.as_ref().map(|c| c.output).unwrap_or(false);
+        let outputs = Outputs {
+            lpx: sink(|r| &mut r.lpx),
+            note: sink(|r| &mut r.note),
+            ctl: sink(|r| &mut r.ctl),
+            clock: if clock_out {
+                Some(sink(|r| &mut r.clock))
+            } else {
+                None
+            },
+            mod_host: Some(Box::new(RecordingSink {
+                log: recording.clone(),
+                select: |r| &mut r.mod_host,
+                elapsed: elapsed.clone(),
+            })),
+        };
+        let start = Instant::now();
+        let mut controller = Controller::new(config, outputs);
+        controller.start(start)?;
+        Ok(Self {
+            controller,
+            recording,
+            elapsed,
+            start,
+        })
+    }
+
+    fn now(&self) -> Instant {
+        self.start + self.elapsed.get()
+    }
+
+    /// Let simulated time pass, doing everything that falls due
+    pub fn wait(&mut self, duration: Duration) -> Result<(), Box<dyn Error>> {
+        let until = self.now() + duration;
+        while let Some(due) = self.controller.next_due() {
+            if due > until {
+                break;
+            }
+            self.elapsed.set(due.max(self.now()) - self.start);
+            self.controller.tick(due)?;
+        }
+        self.elapsed.set(until - self.start);
+        Ok(())
+    }
+
+    pub fn event(&mut self, event: Event) -> Result<(), Box<dyn Error>> {
+        let now = self.now();
+        self.controller.handle(event, now)
+    }
+
+    /// Run one line of a script
+    pub fn step(&mut self, line: &str) -> Result<(), Box<dyn Error>> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(());
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let arg = |i: usize| -> Result<u8, Box<dyn Error>> {
+            Ok(words
+                .get(i)
+                .ok_or_else(|| format!("Missing argument: {line}"))?
+                .parse::<u8>()?)
+        };
+        match words[0] {
+            "press" => {
+                let velocity = if words.len() > 2 { arg(2)? } else { 127 };
+                self.event(Event::Lpx(vec![144, arg(1)?, velocity]))
+            }
+            "release" => self.event(Event::Lpx(vec![144, arg(1)?, 0])),
+            "pressure" => self.event(Event::Lpx(vec![160, arg(1)?, arg(2)?])),
+            "control" => self.event(Event::Lpx(vec![176, arg(1)?, arg(2)?])),
+            "feedback" => self.event(Event::Feedback(vec![144, arg(1)?, arg(2)?])),
+            "clock" => self.event(Event::Clock(vec![clock::CLOCK])),
+            "start" => self.event(Event::Clock(vec![clock::START])),
+            "continue" => self.event(Event::Clock(vec![clock::CONTINUE])),
+            "stop" => self.event(Event::Clock(vec![clock::STOP])),
+            "unplug" => self.event(Event::LpxGone),
+            "replug" => self.event(Event::LpxBack),
+            "quit" => {
+                let now = self.now();
+                self.controller.stop(now)
+            }
+            "wait" => {
+                let ms: u64 = words
+                    .get(1)
+                    .ok_or_else(|| format!("Missing argument: {line}"))?
+                    .parse()?;
+                self.wait(Duration::from_millis(ms))
+            }
+            _ => Err(format!("Unknown command: {line}").into()),
+        }
+    }
+
+    /// Everything sent so far
+    pub fn recording(&self) -> Recording {
+        self.recording.borrow().clone()
+    }
+
+    /// Run a script and return everything sent
+    pub fn run(mut self, script: &str) -> Result<Recording, Box<dyn Error>> {
+        for line in script.lines() {
+            self.step(line)?;
+        }
+        // The last LED changes
+        self.wait(led_state::FRAME)?;
+        Ok(self.recording())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The MIDI sent to an output, without the times
+    fn messages(log: &Log) -> Vec<Vec<u8>> {
+        log.iter().map(|(_, m)| m.clone()).collect()
+    }
+
+    fn simulate(config: &str, script: &str) -> Recording {
+        let config = Config::parse_json(config).unwrap();
+        Simulator::new(config).unwrap().run(script).unwrap()
+    }
+
+    const TWO_SECTIONS: &str = r#"[
+        {"pads": [11, 12], "main_colour": [1, 0, 0], "active_colour": [0, 1, 0], "midi_note": 60},
+        {"pads": [], "main_colour": [0, 0, 1], "active_colour": [0, 1, 1], "midi_note": 62}
+    ]"#;
+
+    #[test]
+    fn start_up() {
+        let recording = simulate(TWO_SECTIONS, "");
+        // Ask the mode, then programmer mode
+        assert_eq!(recording.lpx[0].1, vec![240, 0, 32, 41, 2, 12, 0, 247]);
+        assert_eq!(recording.lpx[1].1, vec![240, 0, 32, 41, 2, 12, 0, 127, 247]);
+        let leds = recording.leds();
+        assert_eq!(leds[&11], [1, 0, 0].into());
+        assert!(recording.note.is_empty());
+    }
+
+    #[test]
+    fn press_and_release() {
+        let recording = simulate(TWO_SECTIONS, "press 11 100\nrelease 11");
+        assert_eq!(
+            messages(&recording.note),
+            vec![vec![144, 60, 100], vec![144, 60, 0]]
+        );
+        assert_eq!(recording.leds()[&12], [1, 0, 0].into());
+    }
+
+    #[test]
+    fn active_colour() {
+        let recording = simulate(TWO_SECTIONS, "press 12");
+        let leds = recording.leds();
+        assert_eq!(leds[&11], [0, 1, 0].into());
+        assert_eq!(leds[&12], [0, 1, 0].into());
+        assert_eq!(leds[&13], [0, 0, 1].into());
+    }
+
+    #[test]
+    fn channel_and_note_offset() {
+        let mut config = Config::parse_json(TWO_SECTIONS).unwrap();
+        config.channel = Some(3);
+        config.note_offset = -12;
+        let mut simulator = Simulator::new(config).unwrap();
+        // Feedback is for the notes as they are sent
+        simulator.event(Event::Feedback(vec![144, 50, 100])).unwrap();
+        let recording = simulator.run("press 11 100").unwrap();
+        assert_eq!(messages(&recording.note), vec![vec![146, 48, 100]]);
+        assert_eq!(recording.leds()[&13], [0, 1, 1].into());
+    }
+
+    #[test]
+    fn polyphonic() {
+        // The second press plays the note again.  Releasing one pad
+        // does not stop the note the other is holding
+        let config = r#"[
+            {"pads": [11, 12], "main_colour": [1, 0, 0], "active_colour": [0, 1, 0], "midi_note": 60, "polyphonic": true},
+            {"pads": [], "main_colour": [0, 0, 1], "active_colour": [0, 1, 1], "midi_note": 62}
+        ]"#;
+        let recording = simulate(config, "press 11 100\npress 12 90\nrelease 11");
+        assert_eq!(
+            messages(&recording.note),
+            vec![vec![144, 60, 100], vec![144, 60, 0], vec![144, 60, 90]]
+        );
+        assert_eq!(recording.leds()[&11], [0, 1, 0].into());
+        let recording = simulate(config, "press 11 100\npress 12 90\nrelease 11\nrelease 12");
+        assert_eq!(messages(&recording.note).last(), Some(&vec![144, 60, 0]));
+        assert_eq!(messages(&recording.note).len(), 4);
+    }
+
+    #[test]
+    fn choke_group() {
+        // The closed hi-hat stops the open one
+        let config = r#"[
+            {"pads": [11], "main_colour": [1, 0, 0], "active_colour": [0, 1, 0], "midi_note": 46, "choke_group": 1},
+            {"pads": [12], "main_colour": [1, 0, 0], "active_colour": [0, 1, 0], "midi_note": 42, "choke_group": 1},
+            {"pads": [], "main_colour": [0, 0, 1], "active_colour": [0, 1, 1], "midi_note": 36}
+        ]"#;
+        let recording = simulate(config, "press 11\npress 13\npress 12\nrelease 11");
+        assert_eq!(
+            messages(&recording.note),
+            vec![vec![144, 46, 127], vec![144, 36, 127], vec![144, 46, 0], vec![144, 42, 127]]
+        );
+        let leds = recording.leds();
+        assert_eq!(leds[&11], [1, 0, 0].into());
+        assert_eq!(leds[&12], [0, 1, 0].into());
+    }
+
+    #[test]
+    fn radio() {
+        let config = r#"[
+            {"pads": [11, 21], "main_colour": [1, 0, 0], "active_colour": [0, 1, 0], "midi_note": 60, "choke_group": 2, "radio": true},
+            {"pads": [12], "main_colour": [1, 0, 0], "active_colour": [0, 1, 0], "midi_note": 62, "choke_group": 2, "radio": true},
+            {"pads": [], "main_colour": [0, 0, 1], "active_colour": [0, 1, 1], "midi_note": 36}
+        ]"#;
+        // On until the next press, or another in the group
+        let recording = simulate(config, "press 11\nrelease 11\npress 12\npress 12");
+        assert_eq!(
+            messages(&recording.note),
+            vec![vec![144, 60, 127], vec![144, 60, 0], vec![144, 62, 127], vec![144, 62, 0]]
+        );
+        let recording = simulate(config, "press 11\nrelease 11\npress 21\nrelease 21");
+        assert_eq!(messages(&recording.note).len(), 2);
+        let recording = simulate(config, "press 11\nrelease 11");
+        assert_eq!(recording.leds()[&21], [0, 1, 0].into());
+    }
+
+    #[test]
+    fn edit_mode_releases() {
+        // A pad held when edit mode starts is released then, as its
+        // release goes to the editor
+        let mut config = Config::parse_json(TWO_SECTIONS).unwrap();
+        config.file = Some("pads.json".to_string());
+        let script = "press 11\ncontrol 95 127\ncontrol 96 127\nrelease 11";
+        let recording = Simulator::new(config).unwrap().run(script).unwrap();
+        assert_eq!(
+            messages(&recording.note),
+            vec![vec![144, 60, 127], vec![144, 60, 0]]
+        );
+    }
+
+    #[test]
+    fn sequencer_mode() {
+        // A pad held when sequencer mode starts is released then.  In
+        // sequencer mode mapped buttons still work
+        let config = r#"{
+            "sections": [
+                {"pads": [11], "main_colour": [1, 0, 0], "active_colour": [0, 1, 0], "midi_note": 60},
+                {"pads": [], "main_colour": [0, 0, 1], "active_colour": [0, 1, 1], "midi_note": 62}
+            ],
+            "sequencer": {},
+            "buttons": [{"button": 91, "cc": 20}]
+        }"#;
+        let script = "press 11\ncontrol 98 127\nrelease 11\ncontrol 91 127";
+        let recording = simulate(config, script);
+        assert_eq!(
+            messages(&recording.note),
+            vec![vec![144, 60, 127], vec![144, 60, 0]]
+        );
+        assert_eq!(messages(&recording.ctl), vec![vec![176, 20, 127]]);
+    }
+
+    #[test]
+    fn unplugged() {
+        // Held notes stop when the LPX goes.  When it is back it is
+        // put in programmer mode and every pad is sent again
+        let recording = simulate(TWO_SECTIONS, "press 11\nunplug\nwait 10\nreplug");
+        assert_eq!(
+            messages(&recording.note),
+            vec![vec![144, 60, 127], vec![144, 60, 0]]
+        );
+        let programmer_mode = vec![240, 0, 32, 41, 2, 12, 0, 127, 247];
+        let last = recording.lpx.len() - 1;
+        assert_eq!(recording.lpx[last - 1].1, programmer_mode);
+        let leds = Led::from_sysex(&recording.lpx[last].1);
+        assert_eq!(leds.len(), 64);
+        assert!(leds.contains(&(11, [1, 0, 0].into())));
+    }
+
+    #[test]
+    fn quit() {
+        let config = Config::parse_json(TWO_SECTIONS).unwrap();
+        let mut simulator = Simulator::new(config).unwrap();
+        // The LPX was in the Note layout
+        let answer = vec![240, 0, 32, 41, 2, 12, 0, 1, 247];
+        simulator.event(Event::Lpx(answer)).unwrap();
+        let recording = simulator.run("press 11\nquit").unwrap();
+        let notes = messages(&recording.note);
+        assert_eq!(notes[1], vec![144, 60, 0]);
+        assert_eq!(notes[2], vec![176, 123, 0]);
+        assert_eq!(notes.len(), 18);
+        let lpx = messages(&recording.lpx);
+        assert_eq!(lpx[lpx.len() - 1], vec![240, 0, 32, 41, 2, 12, 0, 1, 247]);
+        let leds = recording.leds();
+        assert_eq!(leds.len(), 81);
+        assert!(leds.values().all(|led| *led == Led::default()));
+    }
+
+    /// An output that has gone
+    struct BrokenSink;
+
+    impl MidiSink for BrokenSink {
+        fn send(&mut self, _: &[u8]) -> Result<(), Box<dyn Error>> {
+            Err("gone".into())
+        }
+    }
+
+    #[test]
+    fn quit_with_outputs_gone() {
+        let config = Config::parse_json(TWO_SECTIONS).unwrap();
+        let recording = Rc::new(RefCell::new(Recording::default()));
+        let lpx = RecordingSink {
+            log: recording.clone(),
+            select: |r| &mut r.lpx,
+            elapsed: Rc::new(Cell::new(Duration::ZERO)),
+        };
+        let outputs = Outputs {
+            lpx: Box::new(lpx),
+            note: Box::new(BrokenSink),
+            ctl: Box::new(BrokenSink),
+            clock: None,
+            mod_host: None,
+        };
+        let mut controller = Controller::new(config, outputs);
+        let now = Instant::now();
+        controller.start(now).unwrap();
+        assert!(controller.handle(Event::Lpx(vec![144, 11, 127]), now).is_err());
+
+        // The LPX is still cleared and put back in its mode
+        assert!(controller.stop(now).is_err());
+        let recording = recording.borrow();
+        let lpx = messages(&recording.lpx);
+        assert_eq!(lpx[lpx.len() - 1], vec![240, 0, 32, 41, 2, 12, 0, 0, 247]);
+        assert!(recording.leds().values().all(|led| *led == Led::default()));
+    }
+
+    #[test]
+    fn two_pads_held() {
+        // The note stops, and the colour is restored, when the last
+        // pad is released
+        let recording = simulate(TWO_SECTIONS, "press 11\npress 12\nrelease 11");
+        assert_eq!(messages(&recording.note), vec![vec![144, 60, 127]]);
+        assert_eq!(recording.leds()[&11], [0, 1, 0].into());
+    }
+
+    #[test]
+    fn arpeggio() {
+        let config = r#"[
+            {"pads": [], "main_colour": [0, 0, 1], "active_colour": [0, 1, 1],
+             "midi_note": 60, "chord": [64, 67], "arpeggio": {"step_ms": 100}}
+        ]"#;
+        let recording = simulate(config, "press 44 90\nwait 150\nrelease 44");
+        assert_eq!(
+            recording.note,
+            vec![
+                (0, vec![144, 60, 90]),
+                (100, vec![144, 60, 0]),
+                (100, vec![144, 64, 90]),
+                (150, vec![144, 64, 0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn controls_and_feedback() {
+        let recording = simulate(TWO_SECTIONS, "control 91 127\nfeedback 62 100");
+        assert_eq!(messages(&recording.ctl), vec![vec![176, 91, 127]]);
+        assert_eq!(recording.leds()[&88], [0, 1, 1].into());
+    }
+
+    #[test]
+    fn unknown_command() {
+        let config = Config::parse_json(TWO_SECTIONS).unwrap();
+        assert!(Simulator::new(config).unwrap().step("jump 11").is_err());
+    }
+}
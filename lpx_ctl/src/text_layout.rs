@@ -0,0 +1,151 @@
+//! The text layout format that the Perl script `make_cfg` converts to
+//! section JSON.  One definition a line.  Blank lines, and lines
+//! starting with `#`, are ignored
+//! * `<r> <g> <b> <colour name>`: A colour.  Each in 0-127
+//! * `<section name> <main colour> <active colour> <MIDI> [<pads>]`:
+//!   A section.  E.g. `section1 red green 60 [78, 79, 88, 89]`.  A
+//!   section with no pads (`[]`) is the default section
+//! * `MIDI_ADJ <N>`: `N` is added to the MIDI note of every section
+//!   after it
+//!
+//! Colours can be defined before or after the sections that use
+//! them.  The sections are in the order of their names, as `make_cfg`
+//! writes them
+use crate::colour::Palette;
+use crate::lpx_ctl_error::LpxCtlError;
+use crate::section::Section;
+use std::collections::BTreeMap;
+
+/// A section as written, before its colours are looked up
+struct Definition {
+    pads: Vec<u8>,
+    main_colour: String,
+    active_colour: String,
+    midi_note: u8,
+}
+
+/// The pads in `[11, 12, 13]`
+fn parse_pads(list: &str, line: &str) -> Result<Vec<u8>, LpxCtlError> {
+    let err = || LpxCtlError::BadPads(line.to_string());
+    let list = list
+        .trim()
+        .strip_prefix('[')
+        .and_then(|l| l.strip_suffix(']'))
+        .ok_or_else(err)?;
+    let mut result: Vec<u8> = vec![];
+    for pad in list.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+        let pad: u8 = pad.parse().map_err(|_| err())?;
+        if !Section::valid_pad(pad) || result.contains(&pad) {
+            return Err(err());
+        }
+        result.push(pad);
+    }
+    Ok(result)
+}
+
+pub fn parse(input: &str) -> Result<Vec<Section>, LpxCtlError> {
+    let mut palette = Palette::default();
+    let mut definitions: BTreeMap<String, Definition> = BTreeMap::new();
+    let mut midi_adj: i32 = 0;
+    for (index, line) in input.lines().enumerate() {
+        let bad_line = || LpxCtlError::BadLayoutLine(index + 1, line.to_string());
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        if trimmed.starts_with(|c: char| c.is_ascii_digit()) {
+            palette.parse_line(trimmed)?;
+            continue;
+        }
+        if let Some(adj) = trimmed.strip_prefix("MIDI_ADJ") {
+            midi_adj = adj.trim().parse().map_err(|_| bad_line())?;
+            continue;
+        }
+
+        // A section
+        let (words, pads) = trimmed.split_at(trimmed.find('[').ok_or_else(bad_line)?);
+        let words: Vec<&str> = words.split_whitespace().collect();
+        if words.len() != 4 {
+            return Err(bad_line());
+        }
+        let midi_note = words[3].parse::<i32>().map_err(|_| bad_line())? + midi_adj;
+        let definition = Definition {
+            pads: parse_pads(pads, trimmed)?,
+            main_colour: words[1].to_string(),
+            active_colour: words[2].to_string(),
+            midi_note: u8::try_from(midi_note)
+                .ok()
+                .filter(|n| *n < 128)
+                .ok_or_else(bad_line)?,
+        };
+        let name = words[0].to_string();
+        if definitions.contains_key(&name) {
+            return Err(LpxCtlError::DuplicateName(name));
+        }
+        definitions.insert(name, definition);
+    }
+
+    // Every pad in one section
+    let mut owners: BTreeMap<u8, &str> = BTreeMap::new();
+    for (name, definition) in definitions.iter() {
+        for pad in definition.pads.iter() {
+            if let Some(other) = owners.insert(*pad, name) {
+                return Err(LpxCtlError::BadPads(format!("{pad} is in {other} and {name}")));
+            }
+        }
+    }
+
+    let mut result = vec![];
+    for definition in definitions.into_values() {
+        result.push(Section::new(
+            definition.pads,
+            palette.get(&definition.main_colour)?,
+            palette.get(&definition.active_colour)?,
+            definition.midi_note,
+        ));
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layouts() {
+        let layout = "# A layout\n\
+                      127 0 0 red\n\
+                      s2 blue red 60 [12, 13]\n\
+                      MIDI_ADJ -12\n\
+                      s1 red blue 60 [11]\n\
+                      default red red 62 []\n\
+                      0 0 127 blue\n";
+        let sections = parse(layout).unwrap();
+        // In name order
+        assert_eq!(sections[0].pads, Vec::<u8>::new());
+        assert_eq!(sections[0].midi_note, 50);
+        assert_eq!(sections[1].pads, vec![11]);
+        assert_eq!(sections[1].midi_note, 48);
+        assert_eq!(sections[2].pads, vec![12, 13]);
+        assert_eq!(sections[2].main_colour, [0, 0, 127].into());
+        assert_eq!(sections[2].midi_note, 60);
+
+        assert!(matches!(parse("s1 red red 60 [11]"), Err(LpxCtlError::UnknownColour(_))));
+        let red = "1 0 0 red\n";
+        for bad in ["s1 red red 60 [11, 19]", "s1 red red 60 [11, 11]", "s1 red red 60 [11"] {
+            assert!(matches!(parse(&format!("{red}{bad}")), Err(LpxCtlError::BadPads(_))));
+        }
+        assert!(matches!(
+            parse(&format!("{red}s1 red red 60 [11]\ns1 red red 61 [12]")),
+            Err(LpxCtlError::DuplicateName(_))
+        ));
+        assert!(matches!(
+            parse(&format!("{red}s1 red red 60 [11]\ns2 red red 61 [11]")),
+            Err(LpxCtlError::BadPads(_))
+        ));
+        assert!(matches!(
+            parse(&format!("{red}s1 red 60 [11]")),
+            Err(LpxCtlError::BadLayoutLine(2, _))
+        ));
+    }
+}
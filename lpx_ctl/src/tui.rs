@@ -0,0 +1,448 @@
+//! A mirror of the grid in the terminal, for working on layouts
+//! without the LPX in front of you.  Each pad is drawn in the colour
+//! the LPX is showing with the note its section plays, and held pads
+//! are marked.  Pads can be pressed with the mouse, or with the arrow
+//! keys and space or enter, as if they were pressed on the LPX.  `q`,
+//! escape, or control-C quits
+//!
+//! While the mirror has the terminal stderr goes to a pane beside the
+//! grid, so messages do not garble the display.  They are printed
+//! again when it closes
+use crate::controller::MidiSink;
+use crate::device::DeviceKind;
+use crate::event::Event;
+use crate::led::Led;
+use crate::section::Section;
+use crossterm::event::{
+    self as term_event, DisableMouseCapture, EnableMouseCapture, Event as TermEvent, KeyCode,
+    KeyEventKind, KeyModifiers, MouseButton, MouseEventKind,
+};
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use crossterm::ExecutableCommand;
+use ratatui::prelude::*;
+use ratatui::widgets::{Block, Borders, Paragraph, Wrap};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, stdout, BufRead, BufReader};
+use std::os::fd::{FromRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// The size of a pad on the screen
+const CELL_WIDTH: u16 = 7;
+const CELL_HEIGHT: u16 = 3;
+
+/// How long a pad pressed from the keyboard is held
+const KEY_PRESS: Duration = Duration::from_millis(200);
+
+/// How often the screen is redrawn
+const FRAME: Duration = Duration::from_millis(50);
+
+/// The number of lines from stderr kept
+const LOG_LINES: usize = 1000;
+
+/// What the grid is showing.  Shared between the main thread, that
+/// updates it, and the thread that draws it
+#[derive(Debug, Default)]
+pub struct TuiState {
+    /// What each pad's LED is set to
+    leds: HashMap<u8, Led>,
+
+    /// The pads held down, on the LPX or here
+    pressed: HashSet<u8>,
+
+    /// The note each pad's section plays
+    notes: HashMap<u8, u8>,
+
+    /// The last `LOG_LINES` lines written to stderr
+    log: VecDeque<String>,
+}
+
+impl TuiState {
+    pub fn new(sections: &[Section]) -> Self {
+        let mut state = Self::default();
+        state.set_sections(sections);
+        state
+    }
+
+    /// Follow the sections' pads and notes, as the editor changes them
+    pub fn set_sections(&mut self, sections: &[Section]) {
+        self.notes = sections
+            .iter()
+            .flat_map(|s| s.pads().iter().map(|pad| (*pad, s.midi_note)))
+            .collect();
+    }
+
+    /// Follow the pads pressed and released.  `pad` is 11-88
+    pub fn note(&mut self, pad: u8, velocity: u8) {
+        if velocity > 0 {
+            self.pressed.insert(pad);
+        } else {
+            self.pressed.remove(&pad);
+        }
+    }
+}
+
+/// Passes everything on to the LPX, and keeps a copy of the colours
+/// for the mirror
+pub struct MirrorSink {
+    pub lpx: Box<dyn MidiSink>,
+    pub state: Arc<Mutex<TuiState>>,
+}
+
+impl MidiSink for MirrorSink {
+    fn send(&mut self, message: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.state
+            .lock()
+            .unwrap()
+            .leds
+            .extend(Led::from_sysex(message));
+        self.lpx.send(message)
+    }
+}
+
+/// Sends stderr into `TuiState::log` until it is dropped
+struct StderrCapture {
+    /// The real stderr
+    saved: RawFd,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl StderrCapture {
+    fn start(state: Arc<Mutex<TuiState>>) -> io::Result<Self> {
+        let mut pipe = [0; 2];
+        // SAFETY: `pipe` has room for the two descriptors, and the
+        // descriptors used are open
+        let saved = unsafe {
+            if libc::pipe(pipe.as_mut_ptr()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let saved = libc::dup(libc::STDERR_FILENO);
+            if saved < 0 || libc::dup2(pipe[1], libc::STDERR_FILENO) < 0 {
+                let err = io::Error::last_os_error();
+                libc::close(pipe[0]);
+                libc::close(pipe[1]);
+                return Err(err);
+            }
+            libc::close(pipe[1]);
+            saved
+        };
+        // SAFETY: The read end of the pipe is owned by nothing else
+        let pipe = unsafe { File::from_raw_fd(pipe[0]) };
+        let reader = thread::spawn(move || {
+            // Ends when stderr is restored, closing the pipe
+            for line in BufReader::new(pipe).lines().map_while(Result::ok) {
+                let mut state = state.lock().unwrap();
+                state.log.push_back(line);
+                if state.log.len() > LOG_LINES {
+                    state.log.pop_front();
+                }
+            }
+        });
+        Ok(Self {
+            saved,
+            reader: Some(reader),
+        })
+    }
+}
+
+impl Drop for StderrCapture {
+    fn drop(&mut self) {
+        // SAFETY: `saved` is open, and only used here
+        unsafe {
+            libc::dup2(self.saved, libc::STDERR_FILENO);
+            libc::close(self.saved);
+        }
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
+    }
+}
+
+/// The pad drawn at `x`, `y` on the screen, if any.  `grid` is where
+/// the pads are drawn.  Row 8 is at the top
+fn pad_at(grid: Rect, x: u16, y: u16) -> Option<u8> {
+    if x < grid.x || y < grid.y {
+        return None;
+    }
+    let col = (x - grid.x) / CELL_WIDTH;
+    let row = (y - grid.y) / CELL_HEIGHT;
+    if col < 8 && row < 8 {
+        Some(Section::row_col_to_pad(8 - row as u8, col as u8 + 1))
+    } else {
+        None
+    }
+}
+
+/// The screen colour for an LED.  Flashing and pulsing LEDs use the
+/// LPX's palette, so are only marked
+fn led_colour(led: Option<&Led>) -> Color {
+    match led {
+        Some(Led::Rgb { colour }) => Color::Rgb(colour[0] * 2, colour[1] * 2, colour[2] * 2),
+        Some(_) => Color::Gray,
+        None => Color::Black,
+    }
+}
+
+fn draw(frame: &mut Frame, state: &TuiState, cursor: u8) -> Rect {
+    let area = Rect::new(0, 0, CELL_WIDTH * 8 + 2, CELL_HEIGHT * 8 + 2).intersection(frame.size());
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title("LpxCtl (q to quit)");
+    let grid = block.inner(area);
+    frame.render_widget(block, area);
+    for row in 1..=8 {
+        for col in 1..=8 {
+            let pad = Section::row_col_to_pad(row, col);
+            let cell = Rect::new(
+                grid.x + (col as u16 - 1) * CELL_WIDTH,
+                grid.y + (8 - row as u16) * CELL_HEIGHT,
+                CELL_WIDTH,
+                CELL_HEIGHT,
+            )
+            .intersection(grid);
+            let led = state.leds.get(&pad);
+            let background = led_colour(led);
+            let foreground = match background {
+                Color::Rgb(r, g, b) if r as u16 + g as u16 + b as u16 > 255 => Color::Black,
+                Color::Gray => Color::Black,
+                _ => Color::White,
+            };
+            let mut style = Style::default().bg(background).fg(foreground);
+            if pad == cursor {
+                style = style.add_modifier(Modifier::UNDERLINED);
+            }
+            let note = state
+                .notes
+                .get(&pad)
+                .map(|n| n.to_string())
+                .unwrap_or_default();
+            let animation = match led {
+                Some(Led::Flash { .. }) => "~",
+                Some(Led::Pulse { .. }) => "*",
+                _ => "",
+            };
+            let text = if state.pressed.contains(&pad) {
+                format!("[{note}]{animation}")
+            } else {
+                format!("{note}{animation}")
+            };
+            let lines = vec![Line::from(format!("{pad}")), Line::from(text)];
+            frame.render_widget(Paragraph::new(lines).style(style).centered(), cell);
+        }
+    }
+    draw_log(frame, state, area);
+    grid
+}
+
+/// The messages from stderr, beside the grid at `area` or below it,
+/// whichever has more room.  The newest at the bottom
+fn draw_log(frame: &mut Frame, state: &TuiState, grid: Rect) {
+    let size = frame.size();
+    let right = Rect::new(grid.right(), 0, size.width.saturating_sub(grid.right()), size.height);
+    let below = Rect::new(0, grid.bottom(), size.width, size.height.saturating_sub(grid.bottom()));
+    let area = if right.area() >= below.area() { right } else { below };
+    if area.width < 3 || area.height < 3 {
+        return;
+    }
+    let block = Block::default().borders(Borders::ALL).title("Messages");
+    let inner = block.inner(area);
+    // As many of the last lines as fit, wrapped
+    let mut rows = inner.height as usize;
+    let mut lines = vec![];
+    for line in state.log.iter().rev() {
+        let height = line.chars().count().div_ceil(inner.width as usize).max(1);
+        if height > rows {
+            break;
+        }
+        rows -= height;
+        lines.push(Line::from(line.as_str()));
+    }
+    lines.reverse();
+    frame.render_widget(Paragraph::new(lines).wrap(Wrap { trim: false }).block(block), area);
+}
+
+/// The terminal mirror.  It runs in its own thread until this is
+/// dropped, or it is quit
+pub struct Tui {
+    running: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+    state: Arc<Mutex<TuiState>>,
+    capture: Option<StderrCapture>,
+}
+
+impl Tui {
+    /// Take over the terminal and start drawing `state`.  Pads
+    /// pressed here are sent down `tx` as MIDI from the LPX
+    pub fn start(
+        state: Arc<Mutex<TuiState>>,
+        device: DeviceKind,
+        tx: Sender<Event>,
+    ) -> io::Result<Self> {
+        let capture = StderrCapture::start(state.clone())?;
+        enable_raw_mode()?;
+        stdout().execute(EnterAlternateScreen)?;
+        stdout().execute(EnableMouseCapture)?;
+        let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
+        let running = Arc::new(AtomicBool::new(true));
+        let handle = {
+            let running = running.clone();
+            let state = state.clone();
+            thread::spawn(move || {
+                if let Err(err) = run(&mut terminal, &state, device, &tx, &running) {
+                    eprintln!("{err}: Terminal display failed");
+                }
+                let _ = restore_terminal();
+            })
+        };
+        Ok(Self {
+            running,
+            handle: Some(handle),
+            state,
+            capture: Some(capture),
+        })
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+        // The messages again, now they can be read
+        drop(self.capture.take());
+        for line in self.state.lock().unwrap().log.drain(..) {
+            eprintln!("{line}");
+        }
+    }
+}
+
+fn restore_terminal() -> io::Result<()> {
+    stdout().execute(DisableMouseCapture)?;
+    stdout().execute(LeaveAlternateScreen)?;
+    disable_raw_mode()
+}
+
+/// Draw and handle input until `running` is cleared, or the user quits
+fn run(
+    terminal: &mut Terminal<impl Backend>,
+    state: &Mutex<TuiState>,
+    device: DeviceKind,
+    tx: &Sender<Event>,
+    running: &AtomicBool,
+) -> io::Result<()> {
+    let device = device.device();
+    let press = |pad: u8, velocity: u8| {
+        let _ = tx.send(Event::Lpx(vec![144, device.note_from_pad(pad), velocity]));
+    };
+
+    // The pad moved with the arrow keys
+    let mut cursor: u8 = 11;
+    let mut grid = Rect::default();
+
+    // The pad held down by the mouse
+    let mut clicked: Option<u8> = None;
+
+    // Pads pressed from the keyboard, and when to release them
+    let mut releases: Vec<(u8, Instant)> = vec![];
+
+    while running.load(Ordering::Relaxed) {
+        terminal.draw(|frame| grid = draw(frame, &state.lock().unwrap(), cursor))?;
+
+        let now = Instant::now();
+        releases.retain(|(pad, due)| {
+            if *due <= now {
+                press(*pad, 0);
+            }
+            *due > now
+        });
+
+        if !term_event::poll(FRAME)? {
+            continue;
+        }
+        match term_event::read()? {
+            TermEvent::Key(key) if key.kind == KeyEventKind::Press => {
+                let (row, col) = (Section::pad_to_row(cursor), Section::pad_to_col(cursor));
+                // The terminal is raw, so control-C is not a signal
+                let ctrl_c = key.code == KeyCode::Char('c')
+                    && key.modifiers.contains(KeyModifiers::CONTROL);
+                match key.code {
+                    _ if ctrl_c => {
+                        let _ = tx.send(Event::Quit);
+                        break;
+                    }
+                    KeyCode::Char('q') | KeyCode::Esc => {
+                        let _ = tx.send(Event::Quit);
+                        break;
+                    }
+                    KeyCode::Up if row < 8 => cursor += 10,
+                    KeyCode::Down if row > 1 => cursor -= 10,
+                    KeyCode::Right if col < 8 => cursor += 1,
+                    KeyCode::Left if col > 1 => cursor -= 1,
+                    KeyCode::Char(' ') | KeyCode::Enter
+                        if !releases.iter().any(|(pad, _)| *pad == cursor) =>
+                    {
+                        press(cursor, 127);
+                        releases.push((cursor, Instant::now() + KEY_PRESS));
+                    }
+                    _ => (),
+                }
+            }
+            TermEvent::Mouse(mouse) => match mouse.kind {
+                MouseEventKind::Down(MouseButton::Left) => {
+                    if let Some(pad) = pad_at(grid, mouse.column, mouse.row) {
+                        press(pad, 127);
+                        clicked = Some(pad);
+                        cursor = pad;
+                    }
+                }
+                MouseEventKind::Up(MouseButton::Left) => {
+                    if let Some(pad) = clicked.take() {
+                        press(pad, 0);
+                    }
+                }
+                _ => (),
+            },
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clicks_on_pads() {
+        let grid = Rect::new(1, 1, CELL_WIDTH * 8, CELL_HEIGHT * 8);
+        assert_eq!(pad_at(grid, 1, 1), Some(81));
+        assert_eq!(pad_at(grid, CELL_WIDTH * 8, CELL_HEIGHT * 8), Some(18));
+        assert_eq!(pad_at(grid, CELL_WIDTH + 1, CELL_HEIGHT * 7 + 1), Some(12));
+        assert_eq!(pad_at(grid, 0, 1), None);
+        assert_eq!(pad_at(grid, CELL_WIDTH * 8 + 1, 1), None);
+    }
+
+    #[test]
+    fn messages_beside_the_grid() {
+        let mut state = TuiState::default();
+        state.log.extend(["First".to_string(), "Second".to_string()]);
+        let mut terminal = Terminal::new(backend::TestBackend::new(100, 26)).unwrap();
+        terminal.draw(|frame| {
+            draw(frame, &state, 11);
+        })
+        .unwrap();
+        let buffer = terminal.backend().buffer();
+        let row = |y| (58..100).map(|x| buffer.get(x, y).symbol()).collect::<String>();
+        assert!(row(0).contains("Messages"));
+        assert!(row(1).starts_with("│First "));
+        assert!(row(2).starts_with("│Second "));
+    }
+}
@@ -0,0 +1,99 @@
+//! What is done with the velocity of a pad press before it is sent.
+//! Set for all sections in the configuration file, or for one section
+use serde::{Deserialize, Serialize};
+
+/// How the velocity from the pad (0-127) maps to the velocity sent
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Curve {
+    /// Unchanged
+    #[default]
+    Linear,
+
+    /// Soft presses are softer: The square of the velocity
+    Exponential,
+
+    /// Soft presses are louder: log(1 + 9v) / log(10)
+    Logarithmic,
+}
+
+fn default_min() -> u8 {
+    1
+}
+
+fn default_max() -> u8 {
+    127
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct VelocityConfig {
+    /// If set every note is sent with this velocity
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fixed: Option<u8>,
+
+    #[serde(default)]
+    pub curve: Curve,
+
+    /// The curve is scaled to run from `min` to `max`
+    #[serde(default = "default_min")]
+    pub min: u8,
+    #[serde(default = "default_max")]
+    pub max: u8,
+
+    /// If true the brightness of the active colour follows the
+    /// velocity sent
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub colour: bool,
+}
+
+impl VelocityConfig {
+    /// The velocity to send for a pad pressed with `velocity`.  Zero,
+    /// a release, stays zero
+    pub fn apply(&self, velocity: u8) -> u8 {
+        if velocity == 0 {
+            return 0;
+        }
+        if let Some(fixed) = self.fixed {
+            return fixed.clamp(1, 127);
+        }
+        // 1-127 to 0-1
+        let x = (velocity.min(127) - 1) as f64 / 126.0;
+        let y = match self.curve {
+            Curve::Linear => x,
+            Curve::Exponential => x * x,
+            Curve::Logarithmic => (1.0 + 9.0 * x).log10(),
+        };
+        let min = self.min.clamp(1, 127) as f64;
+        let max = self.max.clamp(1, 127) as f64;
+        (min + y * (max - min)).round() as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(json: &str) -> VelocityConfig {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn curves() {
+        let linear = config("{}");
+        assert_eq!(linear.apply(0), 0);
+        assert_eq!(linear.apply(1), 1);
+        assert_eq!(linear.apply(127), 127);
+        assert_eq!(config(r#"{"fixed": 100}"#).apply(20), 100);
+        assert_eq!(config(r#"{"fixed": 100}"#).apply(0), 0);
+
+        let exponential = config(r#"{"curve": "exponential"}"#);
+        let logarithmic = config(r#"{"curve": "logarithmic"}"#);
+        assert!(exponential.apply(64) < linear.apply(64));
+        assert!(logarithmic.apply(64) > linear.apply(64));
+        assert_eq!(logarithmic.apply(127), 127);
+
+        let clamped = config(r#"{"min": 40, "max": 100}"#);
+        assert_eq!(clamped.apply(1), 40);
+        assert_eq!(clamped.apply(127), 100);
+    }
+}